@@ -0,0 +1,9 @@
+use std::path::Path;
+
+fn main() {
+    let out_dir = std::env::var("OUT_DIR").unwrap();
+    let generated = Path::new(&out_dir).join("generated.rs");
+    std::fs::write(generated, "pub fn it_panic_generated() {\n    panic!()\n}\n").unwrap();
+    println!("cargo:rustc-env=GENERATED_FILE=generated.rs");
+    println!("cargo:rerun-if-changed=build.rs");
+}
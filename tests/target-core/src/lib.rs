@@ -0,0 +1,18 @@
+// The core of tests/target-lib, it has only what unpanic needs to find the panic: the calls to
+// `core::panicking::panic`
+#![allow(internal_features)]
+#![feature(no_core, lang_items)]
+#![no_core]
+
+#[lang = "sized"]
+pub trait Sized {}
+
+#[lang = "copy"]
+pub trait Copy {}
+
+pub mod panicking {
+    #[allow(clippy::empty_loop)]
+    pub fn panic(_expr: &'static str) -> ! {
+        loop {}
+    }
+}
@@ -0,0 +1,34 @@
+// Checked with `--target x86_64-unpanic-none.json`: only the code of the target passed to cargo is
+// analyzed. The host has another vendor, so the code gated on it is the one of the other targets.
+// There is no rust-std for the target, there the crate is no_core and tests/target-core is its core.
+#![cfg_attr(target_vendor = "unpanic", feature(no_core), no_core)]
+
+#[cfg(target_vendor = "unpanic")]
+fn target_dependent() {
+    core::panicking::panic("only on the target")
+}
+
+#[cfg(not(target_vendor = "unpanic"))]
+fn target_dependent() {}
+
+#[cfg(not(target_vendor = "unpanic"))]
+fn other_target_dependent() {
+    panic!("only on the other targets")
+}
+
+#[cfg(target_vendor = "unpanic")]
+fn other_target_dependent() {}
+
+pub fn it_panic_on_the_target() {
+    #[allow(unused_labels)]
+    'deny_panic: {
+        target_dependent()
+    }
+}
+
+pub fn it_panic_on_other_targets() {
+    #[allow(unused_labels)]
+    'deny_panic: {
+        other_target_dependent()
+    }
+}
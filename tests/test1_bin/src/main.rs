@@ -56,6 +56,23 @@ fn test_if_see_panics_in_trait_1() {
     }
 }
 
+#[allow(dead_code)]
+fn test_if_see_feature_gated_panics() {
+    #[allow(unused_labels)]
+    'deny_panic: {
+        test1_lib::feature_gated();
+    }
+}
+
+#[allow(dead_code, clippy::unnecessary_literal_unwrap)]
+fn test_if_see_unwrap() {
+    #[allow(unused_labels)]
+    'deny_panic: {
+        let a: Option<u8> = None;
+        a.unwrap();
+    }
+}
+
 #[allow(dead_code)]
 fn test_if_ingnore_panic_in_allow_block() {
     #[allow(unused_labels)]
@@ -100,23 +117,23 @@ fn test_higher_order_fn_1() {
     test_higher_order_fn_(|| panic!(), || {});
 }
 
-//// TODO below test do not works
-////#[allow(dead_code)]
-////fn test_higher_order_fn_2_<N: Fn()>(n: N) {
-////    test_higher_order_fn_(n, || {});
-////}
-////
-////#[allow(dead_code)]
-////fn test_higher_order_fn_2<N: Fn()>(n: N) {
-////    test_higher_order_fn_2_(|| panic!());
-////}
+// TODO below test do not works
+//#[allow(dead_code)]
+//fn test_higher_order_fn_2_<N: Fn()>(n: N) {
+//    test_higher_order_fn_(n, || {});
+//}
+//
+//#[allow(dead_code)]
+//fn test_higher_order_fn_2<N: Fn()>(n: N) {
+//    test_higher_order_fn_2_(|| panic!());
+//}
 
 #[allow(dead_code)]
 fn test_higher_order_fn_different_crate_() {
     test1_lib::test_higher_order_fn_different_crate(|| panic!(), || {});
 }
 
-#[allow(dead_code)]
+#[allow(dead_code, clippy::redundant_closure)]
 fn test_higher_order_fn_different_crate_2() {
     test1_lib::test_higher_order_fn_different_crate(|| test1_lib::it_panic(), || {});
 }
@@ -155,18 +172,24 @@ fn test_higher_order_with_trait_1() {
 //}
 
 
+#[allow(dead_code)]
 struct TestStruct2 {}
 impl TestStruct2 {
+    #[allow(dead_code)]
     fn deny_p<T: Fn()>(&self, th: T) {
+        #[allow(unused_labels)]
         'deny_panic: {
             th();
         }
     }
 }
 
+#[allow(dead_code)]
 fn test() {
     let stru = TestStruct {};
     let stru2 = TestStruct2 {};
-    stru2.deny_p(|| {stru.clone();});
+    stru2.deny_p(|| {
+        let _ = stru.clone();
+    });
     //stru.deny_p(|| panic!());
 }
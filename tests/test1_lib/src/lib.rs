@@ -10,6 +10,14 @@ pub fn it_panic() {
     panic!()
 }
 
+#[cfg(feature = "feature_gated")]
+pub fn feature_gated() {
+    panic!()
+}
+
+#[cfg(not(feature = "feature_gated"))]
+pub fn feature_gated() {}
+
 pub fn deny_panic_in_dependency() {
     #[allow(unused_labels)]
     'deny_panic: {
@@ -37,3 +37,10 @@ pub fn it_panic_nested_macro() {
         PanicStruct::panic_now()
     }
 }
+
+pub fn it_panic_generated() {
+    #[allow(unused_labels)]
+    'deny_panic: {
+        build_script_lib::it_panic_generated()
+    }
+}
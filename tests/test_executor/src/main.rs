@@ -13,48 +13,164 @@ fn main() {
         .wait()
         .unwrap();
 
-    let unpanic_path = format!("{}/target/debug/unpanic", porject_root);
-    Command::new("mv")
-        .args([unpanic_path, porject_root.to_string()])
-        .spawn()
-        .unwrap()
-        .wait()
-        .unwrap();
-    let unpanic_path = format!("{}/unpanic", porject_root);
+    let cargo_unpanic_path = format!("{}/target/debug/cargo-unpanic", porject_root);
 
-    let check_test1_with_unpanic_out = Command::new("cargo")
-        .args(["build", "-p", "test1_bin"])
+    // The first analysis build test1_bin, the second one only reuse the recorded rustc
+    // invocations: the tests are run on the output of the second one
+    Command::new(&cargo_unpanic_path)
+        .args(["unpanic", "-p", "test1_bin"])
+        .current_dir(porject_root)
+        .output()
+        .unwrap();
+    let check_test1_with_unpanic_out = Command::new(&cargo_unpanic_path)
+        .args(["unpanic", "-p", "test1_bin"])
         .current_dir(porject_root)
-        .env("RUSTC_WRAPPER", unpanic_path.clone())
-        .env("TARGET_CRATE", "test1_bin")
         .output()
         .unwrap();
 
     let mut check_test1_with_unpanic_stderr =
         String::from_utf8(check_test1_with_unpanic_out.stderr).unwrap();
+    check_test1_with_unpanic_stderr.push_str(&format!(
+        "test1_bin exit status: {:?}\n",
+        check_test1_with_unpanic_out.status.code()
+    ));
 
-    Command::new("rm")
-        .args(["-r", format!("{}/target", porject_root).as_str()])
-        .spawn()
-        .unwrap()
-        .wait()
+    let check_test2_with_unpanic_out = Command::new(&cargo_unpanic_path)
+        .args(["unpanic", "-p", "test2-lib"])
+        .current_dir(porject_root)
+        .output()
+        .unwrap();
+
+    check_test1_with_unpanic_stderr.push_str(
+        String::from_utf8(check_test2_with_unpanic_out.stderr)
+            .unwrap()
+            .as_str(),
+    );
+
+    // unpanic wraps its own analysis pipeline in 'deny_panic blocks and must pass its own check.
+    // The calls into crates that have not been analyzed fail it too, unless the crates are trusted
+    // in unpanic.toml
+    let check_unpanic_out = Command::new(&cargo_unpanic_path)
+        .args(["unpanic", "-p", "unpanic", "--fail-on", "unresolved"])
+        .current_dir(porject_root)
+        .output()
         .unwrap();
+    check_test1_with_unpanic_stderr.push_str(
+        String::from_utf8(check_unpanic_out.stderr)
+            .unwrap()
+            .as_str(),
+    );
+    check_test1_with_unpanic_stderr.push_str(&format!(
+        "unpanic exit status: {:?}\n",
+        check_unpanic_out.status.code()
+    ));
 
-    let check_test2_with_unpanic_out = Command::new("cargo")
-        .args(["build", "-p", "test2-lib"])
+    // The self check is worth something only if the 'deny_panic blocks of the pipeline are found
+    let check_unpanic_json_out = Command::new(&cargo_unpanic_path)
+        .args(["unpanic", "-p", "unpanic", "--format", "json"])
         .current_dir(porject_root)
-        .env("RUSTC_WRAPPER", &unpanic_path)
-        .env("TARGET_CRATE", "test2-lib")
         .output()
         .unwrap();
+    // No report if unpanic could not run, the test below fails
+    let unpanic_report: serde_json::Value =
+        serde_json::from_slice(&check_unpanic_json_out.stdout).unwrap_or_default();
+    check_test1_with_unpanic_stderr.push_str(&format!(
+        "unpanic deny blocks: {}\n",
+        unpanic_report["deny-blocks"].as_array().map_or(0, Vec::len)
+    ));
 
+    // The json report goes to stdout
+    let check_test2_json_out = Command::new(&cargo_unpanic_path)
+        .args(["unpanic", "-p", "test2-lib", "--format", "json"])
+        .current_dir(porject_root)
+        .output()
+        .unwrap();
     check_test1_with_unpanic_stderr.push_str(
-        String::from_utf8(check_test2_with_unpanic_out.stderr)
+        String::from_utf8(check_test2_json_out.stdout)
             .unwrap()
             .as_str(),
     );
 
+    let check_test2_sarif_out = Command::new(&cargo_unpanic_path)
+        .args(["unpanic", "-p", "test2-lib", "--format", "sarif"])
+        .current_dir(porject_root)
+        .output()
+        .unwrap();
+    check_test1_with_unpanic_stderr.push_str(
+        String::from_utf8(check_test2_sarif_out.stdout)
+            .unwrap()
+            .as_str(),
+    );
+
+    let check_test2_junit_out = Command::new(&cargo_unpanic_path)
+        .args(["unpanic", "-p", "test2-lib", "--format", "junit"])
+        .current_dir(porject_root)
+        .output()
+        .unwrap();
+    check_test1_with_unpanic_stderr.push_str(
+        String::from_utf8(check_test2_junit_out.stdout)
+            .unwrap()
+            .as_str(),
+    );
+
+    let check_test2_rustc_json_out = Command::new(&cargo_unpanic_path)
+        .args(["unpanic", "-p", "test2-lib", "--format", "rustc-json"])
+        .current_dir(porject_root)
+        .output()
+        .unwrap();
+    check_test1_with_unpanic_stderr.push_str(
+        String::from_utf8(check_test2_rustc_json_out.stdout)
+            .unwrap()
+            .as_str(),
+    );
+
+    // The violations of test1_bin written in a baseline are not reported anymore
+    let baseline = format!("{}/target/unpanic-test-baseline.json", porject_root);
+    Command::new(&cargo_unpanic_path)
+        .args(["unpanic", "-p", "test1_bin", "--write-baseline", &baseline])
+        .current_dir(porject_root)
+        .output()
+        .unwrap();
+    let check_test1_baseline_out = Command::new(&cargo_unpanic_path)
+        .args(["unpanic", "-p", "test1_bin", "--baseline", &baseline])
+        .current_dir(porject_root)
+        .output()
+        .unwrap();
+    check_test1_with_unpanic_stderr.push_str(
+        String::from_utf8(check_test1_baseline_out.stderr)
+            .unwrap()
+            .as_str(),
+    );
+    check_test1_with_unpanic_stderr.push_str(&format!(
+        "test1_bin with baseline exit status: {:?}\n",
+        check_test1_baseline_out.status.code()
+    ));
+
+    // The crates are analyzed for the target passed with --target, a custom target that has
+    // another vendor than the host. Only the code gated on the target vendor can panic.
+    let target_spec = format!("{}/tests/target-lib/x86_64-unpanic-none.json", porject_root);
+    let check_target_out = Command::new(&cargo_unpanic_path)
+        .args(["unpanic", "-p", "target-lib", "--target", &target_spec])
+        .current_dir(porject_root)
+        .output()
+        .unwrap();
+    check_test1_with_unpanic_stderr
+        .push_str(String::from_utf8(check_target_out.stderr).unwrap().as_str());
+    // Without --target the same crate is analyzed for the host
+    let check_host_out = Command::new(&cargo_unpanic_path)
+        .args(["unpanic", "-p", "target-lib"])
+        .current_dir(porject_root)
+        .output()
+        .unwrap();
+    let check_host_stderr = String::from_utf8(check_host_out.stderr).unwrap();
+    check_test1_with_unpanic_stderr.push_str(&format!(
+        "target-lib on the host: target panic {}, other targets panic {}\n",
+        check_host_stderr.contains("::it_panic_on_the_target` has a 'deny_panic block"),
+        check_host_stderr.contains("::it_panic_on_other_targets` has a 'deny_panic block"),
+    ));
+
     println!("\n{}", "TESTS: \n".green().bold());
+    let mut failed = 0;
     for (description, test, should_contain) in TESTS {
         let test = check_test1_with_unpanic_stderr.contains(test);
         let test_pass = matches!((test, should_contain), (true, true) | (false, false));
@@ -62,76 +178,105 @@ fn main() {
             println!("    {}{}", "Ok: ".green(), description);
         } else {
             eprintln!("    {}{}", "Error: ".red(), description);
+            failed += 1;
         }
     }
+    if failed > 0 {
+        eprintln!("\n{} of {} tests failed", failed, TESTS.len());
+        std::process::exit(1);
+    }
 }
 
 /// (Test description, String to test, The string should or should not be in the output)
-const TESTS: [(&str, &str, bool); 18] = [
+const TESTS: [(&str, &str, bool); 39] = [
     (
         "check if can see panics in function from external crates",
-        "test_if_see_panics_in_imported_functions in tests/test1_bin/src/main.rs",
+        "::test_if_see_panics_in_imported_functions` has a \'deny_panic block that can panic",
         true,
     ),
     (
         "check if can see panics in function from same crate",
-        "test_if_see_panics_in_local_functions in tests/test1_bin/src/main.rs",
+        "::test_if_see_panics_in_local_functions` has a \'deny_panic block that can panic",
         true,
     ),
     (
         "check if can see panics methods calls",
-        "test_if_see_panics_in_methods in tests/test1_bin/src/main.rs",
+        "::test_if_see_panics_in_methods` has a \'deny_panic block that can panic",
         true,
     ),
     (
         "check if can see panics assoc fn",
-        "test_if_see_panics_in_assoc_fn in tests/test1_bin/src/main.rs",
+        "::test_if_see_panics_in_assoc_fn` has a \'deny_panic block that can panic",
+        true,
+    ),
+    (
+        "check if can see unwrap",
+        "::test_if_see_unwrap` has a \'deny_panic block that can panic",
         true,
     ),
     (
         "check if ignore allow panic blocks",
-        "ATTENTION ALLOW PANIC",
+        "warning: 'allow_panic block reached from a 'deny_panic block",
+        true,
+    ),
+    (
+        "check if handle traits 1",
+        "warning: 'allow_panic block reached from a 'deny_panic block",
         true,
     ),
-    ("check if handle traits 1", "ATTENTION ALLOW PANIC", true),
     (
         "check if carte name that contains `-` are checked",
-        "it_panic_2 in tests/test2-lib/src/lib.rs",
+        "::it_panic_2` has a \'deny_panic block that can panic",
         true,
     ),
     (
         "can check nested libs",
-        "it_panic_nested in tests/test2-lib/src/lib.rs",
+        "::it_panic_nested` has a \'deny_panic block that can panic",
         true,
     ),
     (
         "can check nested libs with feature",
-        "it_panic_nested_feature in tests/test2-lib/src/lib.rs",
+        "::it_panic_nested_feature` has a \'deny_panic block that can panic",
         true,
     ),
     (
         "can check nested libs with macro",
-        "it_panic_nested_macro in tests/test2-lib/src/lib.rs",
+        "::it_panic_nested_macro` has a \'deny_panic block that can panic",
+        true,
+    ),
+    (
+        "can check code generated by build scripts",
+        "::it_panic_generated` has a \'deny_panic block that can panic",
         true,
     ),
     (
         "can check panics in closures",
-        "check_closures in tests/test1_bin/src/main.rs",
+        "::check_closures` has a \'deny_panic block that can panic",
         true,
     ),
     (
         "can check panics in non local methods 0",
-        "test_if_see_panics_in_trait_0 in tests/test1_bin/src/main.rs",
+        "::test_if_see_panics_in_trait_0` has a \'deny_panic block that can panic",
         true,
     ),
     (
         "can check panics in non local methods 1",
-        "test_if_see_panics_in_trait_1 in tests/test1_bin/src/main.rs",
+        "::test_if_see_panics_in_trait_1` has a \'deny_panic block that can panic",
         true,
     ),
     (
         "can deny_panic block in dependency",
-        "deny_panic_in_dependency in tests/test1_lib/src/lib.rs",
+        "::deny_panic_in_dependency` has a \'deny_panic block that can panic",
+        true,
+    ),
+    (
+        "call frames have the fully qualified path of the methods",
+        "`test1_lib::method_test::MethodTest::method_test` is called here",
+        true,
+    ),
+    (
+        "call frames have the trait of the trait methods",
+        "(via trait `test1_lib::method_test::Trait::trait_0`)",
         true,
     ),
     ("higher order function", "test_higher_order_fn_1", true),
@@ -150,4 +295,86 @@ const TESTS: [(&str, &str, bool); 18] = [
         "test_higher_order_with_trait_1",
         true,
     ),
+    (
+        "check if analyze crates with the features used to compile them",
+        "::test_if_see_feature_gated_panics` has a \'deny_panic block that can panic",
+        true,
+    ),
+    (
+        "report is grouped per crate",
+        "can panic in `test1_bin`",
+        true,
+    ),
+    (
+        "exit status is 1 when a deny_panic block can panic",
+        "test1_bin exit status: Some(1)",
+        true,
+    ),
+    (
+        "unpanic can not panic in its own analysis pipeline",
+        "`unpanic::hir_traverser",
+        false,
+    ),
+    (
+        "exit status is 0 when unpanic checks itself",
+        "unpanic exit status: Some(0)",
+        true,
+    ),
+    (
+        "unpanic finds the 'deny_panic blocks of start, check_crate, second_pass and \
+         check_foreign_calls",
+        "unpanic deny blocks: 4\n",
+        true,
+    ),
+    (
+        "the violations in the baseline are not reported",
+        "in the baseline",
+        true,
+    ),
+    (
+        "exit status is 0 when all the violations are in the baseline",
+        "test1_bin with baseline exit status: Some(0)",
+        true,
+    ),
+    ("json report is versioned", "\"version\": 2", true),
+    (
+        "json report has the crate of the violations",
+        "\"crate-name\": \"test2_lib\"",
+        true,
+    ),
+    (
+        "sarif report has a rule for the panics",
+        "\"ruleId\": \"deny-panic/",
+        true,
+    ),
+    (
+        "sarif locations are relative to the workspace root",
+        "\"uri\": \"tests/test2-lib/src/lib.rs\"",
+        true,
+    ),
+    (
+        "junit report has a test case for each deny_panic block",
+        r#"<testcase name="test2_lib::it_panic_2 at tests/test2-lib/src/lib.rs:16:5" classname="test2_lib""#,
+        true,
+    ),
+    (
+        "rustc json diagnostics have a primary span on the deny_panic block",
+        r#""label":"this 'deny_panic block can panic""#,
+        true,
+    ),
+    (
+        "check if the code of the target passed with --target is checked",
+        "::it_panic_on_the_target` has a \'deny_panic block that can panic",
+        true,
+    ),
+    (
+        "check if the code of the other targets is not checked",
+        "::it_panic_on_other_targets` has a \'deny_panic block that can panic",
+        false,
+    ),
+    (
+        "check if the code of the host is checked without --target",
+        "target-lib on the host: target panic false, other targets panic true",
+        true,
+    ),
 ];
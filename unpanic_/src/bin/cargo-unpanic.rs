@@ -0,0 +1,542 @@
+//! `cargo unpanic` subcommand.
+//!
+//! Build the selected packages with `unpanic` installed as `RUSTC_WRAPPER` (or
+//! `RUSTC_WORKSPACE_WRAPPER`) so that every rustc invocation is recorded, then run the analysis on
+//! the recorded targets and print the report.
+//! The wrapper is expected to be in the same directory of this binary (that is what
+//! `cargo install` do).
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+const HELP: &str = "Check that the 'deny_panic blocks of one or more packages can not panic
+
+Usage: cargo unpanic [OPTIONS]
+
+Options:
+  -p, --package <SPEC>      Package to check, can be repeated
+      --workspace           Check all the packages in the workspace
+      --features <FEATURES> Space or comma separated list of features to activate
+      --all-features        Activate all available features
+      --target <TRIPLE>     Check for the target triple
+      --release             Check artifacts in release mode
+      --workspace-wrapper   Record and check only the workspace members, the calls into other
+                            crates are not checked
+      --format <FORMAT>     Format of the report: human (default), json, sarif,
+                            junit or rustc-json
+      --output <FILE>       Write the report to FILE instead of stdout (stderr for human)
+      --fail-on <KINDS>     Comma separated list of what fails the check besides the
+                            'deny_panic blocks that can panic: allow-panics, unresolved
+      --baseline <FILE>     Do not report the violations in the baseline FILE, report the
+                            entries of FILE that have been fixed
+      --write-baseline <FILE>
+                            Write the current violations in the baseline FILE and do not
+                            report them
+  -q, --quiet               Print only the report and the errors
+  -v, --verbose             Log the progress of the analysis, -vv logs also the timings and
+                            the counts of every crate
+  -h, --help                Print help
+
+Environment:
+  UNPANIC_LOG  Log level of the analysis, for all the modules or some of them (eg `debug`
+               or `info,hir_traverser=trace`), overrides -q and -v
+
+Exit status:
+  0  No 'deny_panic block can panic
+  1  A 'deny_panic block can panic, or reaches an 'allow_panic block with
+     `--fail-on allow-panics`
+  2  The check can not be completed: the build or the analysis failed, or a call into a crate
+     that has not been analyzed is found with `--fail-on unresolved`
+";
+
+/// Exit status when the check can not be completed
+const EXIT_INCOMPLETE: i32 = 2;
+
+#[derive(Debug, Default)]
+struct CargoArgs {
+    packages: Vec<String>,
+    workspace: bool,
+    features: Vec<String>,
+    all_features: bool,
+    target: Option<String>,
+    release: bool,
+    quiet: bool,
+    /// Not a cargo arg, verbosity of the analysis
+    verbose: usize,
+    /// Not a cargo arg, unpanic is RUSTC_WORKSPACE_WRAPPER instead of RUSTC_WRAPPER
+    workspace_wrapper: bool,
+    /// Not a cargo arg, format of the report
+    format: Option<String>,
+    /// Not a cargo arg, where the report is written
+    output: Option<PathBuf>,
+    /// Not a cargo arg, what fails the check besides the violations
+    fail_on: Option<String>,
+    /// Not a cargo arg, the violations in it are not reported
+    baseline: Option<PathBuf>,
+    /// Not a cargo arg, where the current violations are written as baseline
+    write_baseline: Option<PathBuf>,
+}
+
+impl CargoArgs {
+    fn parse(args: &[String]) -> Result<Self, String> {
+        let mut ret = Self::default();
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let (name, inline_value) = match arg.split_once('=') {
+                Some((name, value)) if name.starts_with("--") => (name, Some(value.to_string())),
+                _ => (arg.as_str(), None),
+            };
+            let mut value = || {
+                inline_value
+                    .clone()
+                    .or_else(|| args.next().cloned())
+                    .ok_or(format!("a value is required for `{}`", name))
+            };
+            match name {
+                "-p" | "--package" => ret.packages.push(value()?),
+                "--workspace" => ret.workspace = true,
+                "--features" => ret.features.push(value()?),
+                "--all-features" => ret.all_features = true,
+                "--target" => ret.target = Some(value()?),
+                "--release" => ret.release = true,
+                "--baseline" => ret.baseline = Some(PathBuf::from(value()?)),
+                "--write-baseline" => ret.write_baseline = Some(PathBuf::from(value()?)),
+                "-q" | "--quiet" => ret.quiet = true,
+                "-v" | "--verbose" => ret.verbose += 1,
+                "-vv" => ret.verbose += 2,
+                "--workspace-wrapper" => ret.workspace_wrapper = true,
+                "--format" => match value()?.as_str() {
+                    format @ ("human" | "json" | "sarif" | "junit" | "rustc-json") => {
+                        ret.format = Some(format.to_string())
+                    }
+                    format => {
+                        return Err(format!(
+                            "unknown format `{}`, expected human, json, sarif, junit or rustc-json",
+                            format
+                        ))
+                    }
+                },
+                "--output" => ret.output = Some(PathBuf::from(value()?)),
+                "--fail-on" => {
+                    let fail_on = value()?;
+                    for kind in fail_on.split(',').map(str::trim) {
+                        if !matches!(kind, "violations" | "allow-panics" | "unresolved") {
+                            return Err(format!(
+                                "unknown --fail-on `{}`, expected violations, allow-panics or \
+                                 unresolved",
+                                kind
+                            ));
+                        }
+                    }
+                    ret.fail_on = Some(fail_on);
+                }
+                _ => return Err(format!("unexpected argument `{}`", arg)),
+            }
+        }
+        if ret.baseline.is_some() && ret.write_baseline.is_some() {
+            return Err("`--baseline` and `--write-baseline` can not be used together".to_string());
+        }
+        Ok(ret)
+    }
+
+    /// Args for `cargo build`
+    fn to_cargo_args(&self) -> Vec<String> {
+        let mut ret = vec!["build".to_string()];
+        for package in &self.packages {
+            ret.push("--package".to_string());
+            ret.push(package.clone());
+        }
+        if self.workspace {
+            ret.push("--workspace".to_string());
+        }
+        for features in &self.features {
+            ret.push("--features".to_string());
+            ret.push(features.clone());
+        }
+        if self.all_features {
+            ret.push("--all-features".to_string());
+        }
+        if let Some(target) = &self.target {
+            ret.push("--target".to_string());
+            ret.push(target.clone());
+        }
+        if self.release {
+            ret.push("--release".to_string());
+        }
+        if self.quiet {
+            ret.push("--quiet".to_string());
+        }
+        ret
+    }
+}
+
+fn main() {
+    let mut args: Vec<String> = std::env::args().skip(1).collect();
+    // When called as `cargo unpanic` cargo pass the subcommand name as first arg
+    if args.first().map(|s| s.as_str()) == Some("unpanic") {
+        args.remove(0);
+    }
+    if args.iter().any(|s| s == "-h" || s == "--help") {
+        print!("{}", HELP);
+        return;
+    }
+    let cargo_args = match CargoArgs::parse(&args) {
+        Ok(cargo_args) => cargo_args,
+        Err(e) => exit_with_error(&format!("{}\n\n{}", e, HELP)),
+    };
+    let cargo = std::env::var("CARGO").unwrap_or_else(|_| "cargo".to_string());
+    let unpanic = unpanic_path();
+    let metadata = cargo_metadata(&cargo);
+    let workspace_root = metadata_dir(&metadata, "workspace_root");
+    // unpanic has its own target dir, so that every unit is compiled with the wrapper even if it
+    // is fresh in the cargo target dir
+    let target_dir = metadata_dir(&metadata, "target_directory").join("unpanic");
+    let unpanic_dir = std::env::var_os("UNPANIC_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| target_dir.join("no-panic"));
+
+    let mut cargo_build = Command::new(&cargo);
+    cargo_build
+        .args(cargo_args.to_cargo_args())
+        .arg("--target-dir")
+        .arg(&target_dir)
+        .env("UNPANIC_DIR", &unpanic_dir);
+    set_wrapper(&mut cargo_build, &cargo_args, &unpanic);
+    // Only the workspace members are recorded by the workspace wrapper
+    let members = if cargo_args.workspace_wrapper {
+        Some(workspace_manifests(&metadata))
+    } else {
+        None
+    };
+    add_rustc_lib_dir(&mut cargo_build);
+    let artifacts = build(&cargo, cargo_build, members);
+    // The rustc invocations recorded by the wrapper are kept between the builds, this tells the
+    // analysis which ones belong to this build and what are the targets
+    let build_record = serde_json::json!({
+        "targets": selected_packages(&metadata, &cargo_args),
+        "artifacts": artifacts,
+    });
+    std::fs::create_dir_all(&unpanic_dir)
+        .and_then(|_| std::fs::write(unpanic_dir.join("build.json"), build_record.to_string()))
+        .unwrap_or_else(|e| {
+            exit_with_error(&format!(
+                "can not write in {}: {}",
+                unpanic_dir.display(),
+                e
+            ))
+        });
+
+    let mut analyze = Command::new(&unpanic);
+    analyze
+        .arg("--analyze")
+        .arg(&unpanic_dir)
+        .arg("--config")
+        .arg(workspace_root.join("unpanic.toml"))
+        .arg("--workspace-root")
+        .arg(&workspace_root);
+    add_rustc_lib_dir(&mut analyze);
+    if let Some(format) = &cargo_args.format {
+        analyze.arg("--format").arg(format);
+    }
+    // The paths are relative to the dir where cargo unpanic is called
+    let current_dir = std::env::current_dir().unwrap_or_default();
+    if let Some(output) = &cargo_args.output {
+        analyze.arg("--output").arg(current_dir.join(output));
+    }
+    if let Some(baseline) = &cargo_args.baseline {
+        analyze.arg("--baseline").arg(current_dir.join(baseline));
+    }
+    if let Some(write_baseline) = &cargo_args.write_baseline {
+        analyze
+            .arg("--write-baseline")
+            .arg(current_dir.join(write_baseline));
+    }
+    if let Some(fail_on) = &cargo_args.fail_on {
+        analyze.arg("--fail-on").arg(fail_on);
+    }
+    if cargo_args.quiet {
+        analyze.arg("-q");
+    }
+    match cargo_args.verbose {
+        0 => (),
+        1 => {
+            analyze.arg("-v");
+        }
+        _ => {
+            analyze.arg("-vv");
+        }
+    }
+    let status = analyze
+        .status()
+        .unwrap_or_else(|e| exit_with_error(&format!("can not run {}: {}", unpanic.display(), e)));
+    // The exit status of the analysis is the one documented in HELP
+    std::process::exit(status.code().unwrap_or(EXIT_INCOMPLETE));
+}
+
+/// The wrapper is installed alongside `cargo-unpanic`
+fn unpanic_path() -> PathBuf {
+    let current_exe = std::env::current_exe()
+        .unwrap_or_else(|e| exit_with_error(&format!("can not locate cargo-unpanic: {}", e)));
+    let path = current_exe
+        .parent()
+        .map(|dir| dir.join("unpanic"))
+        .unwrap_or_else(|| PathBuf::from("unpanic"));
+    if !path.exists() {
+        exit_with_error(&format!(
+            "can not find the unpanic wrapper at {}",
+            path.display()
+        ));
+    }
+    path
+}
+
+/// unpanic is RUSTC_WRAPPER, or RUSTC_WORKSPACE_WRAPPER with --workspace-wrapper. A RUSTC_WRAPPER
+/// that is already set (eg sccache) is called by unpanic to compile, with --workspace-wrapper it is
+/// left in place and cargo call it for every crate.
+fn set_wrapper(cargo_build: &mut Command, cargo_args: &CargoArgs, unpanic: &Path) {
+    let wrapper = std::env::var("RUSTC_WRAPPER").ok().filter(|wrapper| {
+        !wrapper.is_empty() && Path::new(wrapper).file_stem() != Some("unpanic".as_ref())
+    });
+    // The wrapper would otherwise run `rustc -V` for every crate compiled by an inner wrapper
+    if let Some(version) = rustc_version() {
+        cargo_build.env("UNPANIC_RUSTC_VERSION", version);
+    }
+    if cargo_args.workspace_wrapper {
+        if std::env::var_os("RUSTC_WORKSPACE_WRAPPER").is_some() {
+            exit_with_error(
+                "--workspace-wrapper can not be used when RUSTC_WORKSPACE_WRAPPER is set",
+            );
+        }
+        if wrapper.is_none() {
+            cargo_build.env_remove("RUSTC_WRAPPER");
+        }
+        cargo_build.env("RUSTC_WORKSPACE_WRAPPER", unpanic);
+    } else {
+        if let Some(wrapper) = wrapper {
+            cargo_build.env("UNPANIC_RUSTC_WRAPPER", wrapper);
+        }
+        cargo_build.env("RUSTC_WRAPPER", unpanic);
+    }
+}
+
+/// Version of the rustc called by cargo, as printed by `rustc -V` without the `rustc ` prefix
+fn rustc_version() -> Option<String> {
+    let version = rustc_output(&["-V"])?;
+    Some(
+        version
+            .strip_prefix("rustc ")
+            .unwrap_or(&version)
+            .to_string(),
+    )
+}
+
+/// Trimmed stdout of the rustc called by cargo (RUSTC or the rustc in PATH)
+fn rustc_output(args: &[&str]) -> Option<String> {
+    let rustc = std::env::var_os("RUSTC").unwrap_or_else(|| "rustc".into());
+    let out = Command::new(rustc).args(args).output().ok()?;
+    if !out.status.success() {
+        return None;
+    }
+    Some(String::from_utf8(out.stdout).ok()?.trim().to_string())
+}
+
+/// unpanic is linked to the librustc_driver of its toolchain, that is in the sysroot and not in
+/// the search path of the dynamic linker unless unpanic is run by rustup or cargo
+fn add_rustc_lib_dir(command: &mut Command) {
+    let (var, dir) = if cfg!(windows) {
+        ("PATH", "bin")
+    } else if cfg!(target_os = "macos") {
+        ("DYLD_FALLBACK_LIBRARY_PATH", "lib")
+    } else {
+        ("LD_LIBRARY_PATH", "lib")
+    };
+    let sysroot = match rustc_output(&["--print", "sysroot"]) {
+        Some(sysroot) => PathBuf::from(sysroot),
+        // unpanic reports that it can not be loaded
+        None => return,
+    };
+    let previous = std::env::var_os(var).unwrap_or_default();
+    let dirs = std::iter::once(sysroot.join(dir)).chain(std::env::split_paths(&previous));
+    if let Ok(dirs) = std::env::join_paths(dirs) {
+        command.env(var, dirs);
+    }
+}
+
+/// Run `cargo build` and return the artifacts of the build, only the ones of the packages in
+/// members if any. Cargo report also the artifacts of the units that are fresh, the wrapper is
+/// not called for them.
+fn build(cargo: &str, mut cargo_build: Command, members: Option<Vec<String>>) -> Vec<String> {
+    let mut child = cargo_build
+        .arg("--message-format=json-render-diagnostics")
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap_or_else(|e| exit_with_error(&format!("can not run {}: {}", cargo, e)));
+    let stdout = child.stdout.take().expect("stdout is piped");
+    let mut artifacts = vec![];
+    for line in BufReader::new(stdout).lines().map_while(Result::ok) {
+        let message: serde_json::Value = match serde_json::from_str(&line) {
+            Ok(message) => message,
+            // Build scripts can print on stdout
+            Err(_) => {
+                println!("{}", line);
+                continue;
+            }
+        };
+        let is_member = match (&members, message["manifest_path"].as_str()) {
+            (Some(members), Some(manifest_path)) => members.iter().any(|m| m == manifest_path),
+            _ => true,
+        };
+        if message["reason"] == "compiler-artifact" && is_member {
+            let filenames = message["filenames"].as_array().into_iter().flatten();
+            artifacts.extend(filenames.filter_map(|f| f.as_str()).map(String::from));
+        }
+    }
+    let status = child
+        .wait()
+        .unwrap_or_else(|e| exit_with_error(&format!("can not run {}: {}", cargo, e)));
+    // cargo already printed the errors, a failed build is a check that can not be completed
+    if !status.success() {
+        std::process::exit(EXIT_INCOMPLETE);
+    }
+    artifacts
+}
+
+fn cargo_metadata(cargo: &str) -> serde_json::Value {
+    let out = Command::new(cargo)
+        .args(["metadata", "--format-version", "1", "--no-deps"])
+        .output()
+        .unwrap_or_else(|e| exit_with_error(&format!("can not run {}: {}", cargo, e)));
+    if !out.status.success() {
+        exit_with_error(String::from_utf8_lossy(&out.stderr).trim());
+    }
+    serde_json::from_slice(&out.stdout)
+        .unwrap_or_else(|e| exit_with_error(&format!("invalid cargo metadata: {}", e)))
+}
+
+/// `workspace_root` is where `unpanic.toml` is, `target_directory` is the target dir as resolved
+/// by cargo (`CARGO_TARGET_DIR`, `build.target-dir` in `.cargo/config.toml`..)
+fn metadata_dir(metadata: &serde_json::Value, key: &str) -> PathBuf {
+    metadata[key]
+        .as_str()
+        .map(PathBuf::from)
+        .unwrap_or_else(|| exit_with_error(&format!("no {} in cargo metadata", key)))
+}
+
+/// Manifest paths of the workspace members
+fn workspace_manifests(metadata: &serde_json::Value) -> Vec<String> {
+    // With --no-deps the packages are the workspace members
+    let packages = metadata["packages"].as_array().into_iter().flatten();
+    packages
+        .filter_map(|package| package["manifest_path"].as_str())
+        .map(String::from)
+        .collect()
+}
+
+/// Manifest dir of the packages selected by cargo_args, resolved like cargo do: the packages in
+/// `--package`, every member with `--workspace`, otherwise the member that contains the current
+/// dir or the default members
+fn selected_packages(metadata: &serde_json::Value, cargo_args: &CargoArgs) -> Vec<String> {
+    // With --no-deps the packages are the workspace members
+    let packages = metadata["packages"].as_array().cloned().unwrap_or_default();
+    let manifest_dir = |package: &serde_json::Value| {
+        Path::new(package["manifest_path"].as_str().unwrap_or_default())
+            .parent()
+            .map(|dir| dir.to_string_lossy().to_string())
+            .unwrap_or_default()
+    };
+    let selected: Vec<&serde_json::Value> = if cargo_args.workspace {
+        packages.iter().collect()
+    } else if !cargo_args.packages.is_empty() {
+        packages
+            .iter()
+            .filter(|package| {
+                let name = package["name"].as_str().unwrap_or_default();
+                let version = package["version"].as_str().unwrap_or_default();
+                cargo_args.packages.iter().any(|spec| {
+                    spec == name
+                        || *spec == format!("{}@{}", name, version)
+                        || *spec == format!("{}:{}", name, version)
+                })
+            })
+            .collect()
+    } else {
+        let current_dir = std::env::current_dir().unwrap_or_default();
+        let current = packages
+            .iter()
+            .filter(|package| current_dir.starts_with(manifest_dir(package)))
+            .max_by_key(|package| manifest_dir(package).len());
+        match current {
+            Some(package) => vec![package],
+            None => {
+                let default_members = metadata["workspace_default_members"].as_array();
+                packages
+                    .iter()
+                    .filter(|package| match default_members {
+                        Some(members) => members.contains(&package["id"]),
+                        None => true,
+                    })
+                    .collect()
+            }
+        }
+    };
+    selected.into_iter().map(manifest_dir).collect()
+}
+
+fn exit_with_error(message: &str) -> ! {
+    eprintln!("error: {}", message);
+    std::process::exit(EXIT_INCOMPLETE);
+}
+
+#[test]
+fn test_parse_cargo_args() {
+    let args: Vec<String> = [
+        "-p",
+        "test1_bin",
+        "--package=test2-lib",
+        "--features=a,b",
+        "--release",
+        "--workspace-wrapper",
+        "--format",
+        "json",
+        "--output=report.json",
+        "--fail-on",
+        "allow-panics,unresolved",
+        "-v",
+        "--verbose",
+        "--quiet",
+    ]
+    .iter()
+    .map(|s| s.to_string())
+    .collect();
+    let cargo_args = CargoArgs::parse(&args).unwrap();
+    assert!(cargo_args.workspace_wrapper);
+    assert_eq!(cargo_args.verbose, 2);
+    assert_eq!(cargo_args.format.as_deref(), Some("json"));
+    assert_eq!(cargo_args.output, Some(PathBuf::from("report.json")));
+    assert_eq!(
+        cargo_args.fail_on.as_deref(),
+        Some("allow-panics,unresolved")
+    );
+    let args = ["--format".to_string(), "xml".to_string()];
+    assert!(CargoArgs::parse(&args).is_err());
+    let args = ["--fail-on=warnings".to_string()];
+    assert!(CargoArgs::parse(&args).is_err());
+    let args = ["--baseline=a.json", "--write-baseline=b.json"].map(String::from);
+    assert!(CargoArgs::parse(&args).is_err());
+    let args = ["--write-baseline", "unpanic-baseline.json"].map(String::from);
+    let write_baseline = CargoArgs::parse(&args).unwrap().write_baseline;
+    assert_eq!(write_baseline, Some(PathBuf::from("unpanic-baseline.json")));
+    let cargo_args = cargo_args.to_cargo_args();
+    let expected = [
+        "build",
+        "--package",
+        "test1_bin",
+        "--package",
+        "test2-lib",
+        "--features",
+        "a,b",
+        "--release",
+        "--quiet",
+    ];
+    assert_eq!(cargo_args, expected);
+}
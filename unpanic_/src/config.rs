@@ -0,0 +1,116 @@
+//! Project configuration, loaded from `unpanic.toml` in the workspace root.
+//!
+//! ```toml
+//! # Calls into these crates are assumed to not panic, their 'deny_panic blocks are still checked
+//! trusted = ["getrandom"]
+//! # These crates are not analyzed at all
+//! skip = ["secp256k1", "bitcoin_hashes", "bitcoin", "async_trait", "tracing_attributes"]
+//!
+//! [crates.my-crate]
+//! trusted = true
+//!
+//! [output]
+//! allow-panics = false
+//! ```
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::Path;
+
+use crate::errors::Error;
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct UnpanicConfig {
+    pub trusted: Vec<String>,
+    pub skip: Vec<String>,
+    pub crates: HashMap<String, CrateConfig>,
+    pub output: OutputConfig,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct CrateConfig {
+    pub trusted: bool,
+    pub skip: bool,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct OutputConfig {
+    /// Log the call stacks that lead to an 'allow_panic block
+    pub allow_panics: bool,
+}
+
+impl Default for OutputConfig {
+    fn default() -> Self {
+        Self { allow_panics: true }
+    }
+}
+
+impl UnpanicConfig {
+    /// Load the config at path, if the file do not exist return the default config
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let content = match std::fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => return Err(Error::InvalidConfig(format!("{}: {}", path.display(), e))),
+        };
+        let mut config: Self = toml::from_str(&content)
+            .map_err(|e| Error::InvalidConfig(format!("{}: {}", path.display(), e)))?;
+        config.normalize();
+        Ok(config)
+    }
+
+    /// Crates are identified by crate name, so `my-crate` and `my_crate` are the same crate
+    fn normalize(&mut self) {
+        for crate_name in self.trusted.iter_mut().chain(self.skip.iter_mut()) {
+            *crate_name = crate_name.replace('-', "_");
+        }
+        self.crates = self
+            .crates
+            .drain()
+            .map(|(crate_name, config)| (crate_name.replace('-', "_"), config))
+            .collect();
+    }
+
+    /// Calls into trusted (or skipped) crates are not checked
+    pub fn is_trusted(&self, crate_name: &str) -> bool {
+        self.trusted.iter().any(|s| s == crate_name)
+            || self.crates.get(crate_name).map_or(false, |c| c.trusted)
+            || self.is_skipped(crate_name)
+    }
+
+    pub fn is_skipped(&self, crate_name: &str) -> bool {
+        self.skip.iter().any(|s| s == crate_name)
+            || self.crates.get(crate_name).map_or(false, |c| c.skip)
+    }
+}
+
+#[test]
+fn test_load_config() {
+    let mut config: UnpanicConfig = toml::from_str(
+        r#"
+        trusted = ["getrandom"]
+        skip = ["async-trait"]
+
+        [crates.test1-lib]
+        trusted = true
+        "#,
+    )
+    .unwrap();
+    config.normalize();
+    assert!(config.is_trusted("getrandom"));
+    assert!(config.is_trusted("async_trait"));
+    assert!(config.is_skipped("async_trait"));
+    assert!(!config.is_skipped("getrandom"));
+    assert!(config.is_trusted("test1_lib"));
+    assert!(!config.is_trusted("test1_bin"));
+    assert!(config.output.allow_panics);
+}
+
+#[test]
+fn test_reject_edition() {
+    // The edition is the one cargo passed to rustc, it can not be overridden
+    assert!(toml::from_str::<UnpanicConfig>(r#"default-edition = "2018""#).is_err());
+    assert!(toml::from_str::<UnpanicConfig>("[crates.test1-lib]\nedition = \"2021\"").is_err());
+}
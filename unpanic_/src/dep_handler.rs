@@ -1,67 +1,498 @@
+//! The wrapper records every rustc invocation in `~/target/no-panic/manifest/`, one JSON file (a
+//! [`CrateEntry`]) per compilation unit. The analysis read it back to compile the target crates
+//! and their dependencies again exactly as cargo did.
+//!
+//! The entries are kept between the builds: cargo do not call rustc for the units that are fresh,
+//! so `cargo unpanic` save in `~/target/no-panic/build.json` the artifacts of the last build and
+//! the packages selected for it. The analysis use the entries that produced those artifacts.
+use crate::errors::Error;
 use crate::rustc_arg_handlers::*;
-use std::fs::OpenOptions;
-use std::io::BufRead;
-use std::io::Write;
-
-// Write `carte-name args..` into ~/target/no-panic/deps
-// carte name and each arg are separated by a space
-// each line is a dependecy
-pub fn write_args(args: Vec<String>, path_index: usize) {
-    let mut crate_name = get_crate_name(&args).expect("ERROR: No crate name in rustc args");
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+
+/// Bumped every time the format of CrateEntry change
+pub const MANIFEST_VERSION: u64 = 5;
+pub const MANIFEST_DIR: &str = "manifest";
+pub const BUILD_FILE: &str = "build.json";
+
+/// A crate compiled by cargo with unpanic as RUSTC_WRAPPER
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct CrateEntry {
+    /// MANIFEST_VERSION of the unpanic that wrote the entry
+    pub version: u64,
+    pub crate_name: String,
+    /// Cargo package id spec (`path+file:///dir#name@version`) of the package the crate belong
+    /// to, None if rustc was not called by cargo
+    pub package_id: Option<String>,
+    pub package_version: Option<String>,
+    /// Version of the rustc that compiled the crate
+    pub rustc_version: String,
+    /// Directory where rustc was called, relative paths in args are relative to it
+    pub cwd: PathBuf,
+    /// The rustc args as received by the wrapper
+    pub args: Vec<String>,
+    /// The env that cargo gave to rustc: the CARGO_* variables, OUT_DIR and the variables set by
+    /// the build script with `cargo:rustc-env`
+    pub env: BTreeMap<String, String>,
+    /// Artifacts written by rustc in --out-dir
+    pub outputs: Vec<PathBuf>,
+}
+
+impl CrateEntry {
+    pub fn new(
+        crate_name: String,
+        args: Vec<String>,
+        path_index: usize,
+        rustc_version: String,
+    ) -> std::io::Result<Self> {
+        let env = get_rustc_env();
+        Ok(Self {
+            version: MANIFEST_VERSION,
+            package_id: package_id(&env),
+            package_version: env.get("CARGO_PKG_VERSION").cloned(),
+            rustc_version,
+            cwd: std::env::current_dir()?,
+            env,
+            outputs: get_outputs(&crate_name, &args, path_index),
+            crate_name,
+            args,
+        })
+    }
+
+    /// Cargo give a different extra-filename to every unit, so that units of the same crate (eg
+    /// the lib built for two targets or with different features) do not overwrite each other
+    pub fn unit_name(&self) -> String {
+        let suffix = get_codegen_opt(&self.args, "extra-filename")
+            .or_else(|| get_codegen_opt(&self.args, "metadata").map(|m| format!("-{}", m)))
+            .unwrap_or_default();
+        format!("{}{}", self.crate_name, suffix)
+    }
+
+    /// The entry is the one that produced artifact, or one of its outputs has been hard linked
+    /// (cargo call it uplifted) in artifact. Cargo uplift the binaries of the selected packages
+    /// with the name of the bin target, eg `deps/cargo_unpanic-<hash>` in `cargo-unpanic`.
+    fn produced(&self, artifact: &Path) -> bool {
+        produced(&self.outputs, artifact)
+    }
+
+    fn manifest_dir(&self) -> Option<&str> {
+        self.env.get("CARGO_MANIFEST_DIR").map(|dir| dir.as_str())
+    }
+}
+
+/// Crates of the same name can come from different packages (eg two versions of a dependency,
+/// or a path dependency and a registry one), the package id tell them apart. The wrapper only
+/// see the env that cargo gave to rustc, so the id is built from the manifest dir, as cargo do
+/// for path sources.
+fn package_id(env: &BTreeMap<String, String>) -> Option<String> {
+    let manifest_dir = env.get("CARGO_MANIFEST_DIR")?;
+    let name = env.get("CARGO_PKG_NAME")?;
+    let version = env.get("CARGO_PKG_VERSION")?;
+    Some(format!("path+file://{}#{}@{}", manifest_dir, name, version))
+}
+
+/// The crate of a unit name: the extra-filename start with `-`, that is not allowed in the crate
+/// names
+pub fn unit_crate_name(unit: &str) -> &str {
+    unit.split('-').next().unwrap_or(unit)
+}
+
+fn produced(outputs: &[PathBuf], artifact: &Path) -> bool {
+    outputs
+        .iter()
+        .any(|output| output == artifact || same_file(output, artifact))
+}
+
+/// Uplifted files are hard links of the output
+#[cfg(unix)]
+fn same_file(a: &Path, b: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    match (std::fs::metadata(a), std::fs::metadata(b)) {
+        (Ok(a), Ok(b)) => a.dev() == b.dev() && a.ino() == b.ino(),
+        _ => false,
+    }
+}
+
+#[cfg(not(unix))]
+fn same_file(a: &Path, b: &Path) -> bool {
+    match (a.canonicalize(), b.canonicalize()) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
+
+/// Written by `cargo unpanic` after each build
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct BuildRecord {
+    /// Manifest dir of the packages selected with `--package`, `--workspace` or the default
+    /// members
+    pub targets: Vec<String>,
+    /// Files reported by cargo in the compiler-artifact messages, fresh units included
+    pub artifacts: Vec<PathBuf>,
+}
+
+impl BuildRecord {
+    /// The target crates are the ones in the comma separated env var TARGET_CRATE if set,
+    /// otherwise the crates of the selected packages
+    fn is_target(&self, entry: &CrateEntry) -> bool {
+        match std::env::var("TARGET_CRATE") {
+            Ok(target_crates) => target_crates
+                .split(',')
+                .any(|target_crate| target_crate.trim().replace('-', "_") == entry.crate_name),
+            Err(_) => entry
+                .manifest_dir()
+                .map_or(false, |dir| self.targets.iter().any(|target| target == dir)),
+        }
+    }
+
+    /// An entry written by another version of unpanic can have different fields, only its
+    /// outputs are read. If they can not be read the entry could be one of this build.
+    fn uses(&self, entry: &serde_json::Value) -> bool {
+        let outputs: Option<Vec<PathBuf>> = entry["outputs"].as_array().and_then(|outputs| {
+            outputs
+                .iter()
+                .map(|output| output.as_str().map(PathBuf::from))
+                .collect()
+        });
+        match outputs {
+            Some(outputs) => self
+                .artifacts
+                .iter()
+                .any(|artifact| produced(&outputs, artifact)),
+            None => true,
+        }
+    }
+}
+
+/// Target crates and dependencies read from the manifest
+#[derive(Debug, Default)]
+pub struct Manifest {
+    pub targets: Vec<CrateEntry>,
+    /// Keyed by unit name, a crate can be built more than once in the same build (eg a dependency
+    /// of a build script and of the lib with different features)
+    pub deps: HashMap</* unit name */ String, CrateEntry>,
+}
+
+// Write the CrateEntry of this rustc invocation in ~/target/no-panic/manifest/<unit>.json
+pub fn write_args(
+    args: Vec<String>,
+    path_index: usize,
+    rustc_version: String,
+) -> Result<(), Error> {
+    let crate_name = get_crate_name(&args)?;
     if crate_name == "build_script_build" {
-        return;
+        return Ok(());
+    }
+    let dir = get_unpanic_dir(&args, path_index)?.join(MANIFEST_DIR);
+    let cannot_write = |e: std::io::Error| Error::CannotWriteManifest {
+        path: dir.clone(),
+        reason: e.to_string(),
+    };
+    let entry =
+        CrateEntry::new(crate_name, args, path_index, rustc_version).map_err(cannot_write)?;
+    write_entry(&dir, &entry).map_err(cannot_write)
+}
+
+/// Cargo run many rustc in parallel so each unit has its own file. The entry is written in a
+/// temporary file and then renamed, so the analysis never see a partially written entry.
+fn write_entry(dir: &Path, entry: &CrateEntry) -> std::io::Result<()> {
+    std::fs::create_dir_all(dir)?;
+    let unit_name = entry.unit_name();
+    let tmp = dir.join(format!("{}.json.{}.tmp", unit_name, std::process::id()));
+    std::fs::write(&tmp, serde_json::to_vec(entry)?)?;
+    std::fs::rename(tmp, dir.join(format!("{}.json", unit_name)))
+}
+
+/// The CARGO_* env and, for crates with a build script, OUT_DIR and the env set by the build
+/// script with `cargo:rustc-env`. The cfgs set with `cargo:rustc-cfg` are already in the args.
+fn get_rustc_env() -> BTreeMap<String, String> {
+    let mut env: BTreeMap<String, String> = std::env::vars_os()
+        .filter_map(|(key, value)| Some((key.into_string().ok()?, value.into_string().ok()?)))
+        .filter(|(key, _)| key == "CARGO" || key.starts_with("CARGO_"))
+        .collect();
+    // Cargo set OUT_DIR only for crates that have a build script
+    if let Ok(out_dir) = std::env::var("OUT_DIR") {
+        // Cargo save the build script stdout in OUT_DIR/../output
+        let output = Path::new(&out_dir).with_file_name("output");
+        if let Ok(output) = std::fs::read_to_string(output) {
+            for line in output.lines() {
+                let var = line
+                    .strip_prefix("cargo:rustc-env=")
+                    .or_else(|| line.strip_prefix("cargo::rustc-env="));
+                if let Some((key, value)) = var.and_then(|var| var.split_once('=')) {
+                    env.insert(key.to_string(), value.to_string());
+                }
+            }
+        }
+        env.insert("OUT_DIR".to_string(), out_dir);
+    }
+    env
+}
+
+/// Files in --out-dir named after the crate: `lib<crate><extra-filename>.rlib`, `.rmeta`, the
+/// binary, the dep info..
+fn get_outputs(crate_name: &str, args: &[String], path_index: usize) -> Vec<PathBuf> {
+    let extra_filename = get_codegen_opt(args, "extra-filename").unwrap_or_default();
+    let stem = format!("{}{}", crate_name, extra_filename);
+    let lib_stem = format!("lib{}", stem);
+    let mut outputs: Vec<PathBuf> = std::fs::read_dir(&args[path_index])
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| {
+            let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+            let file_stem = file_name.split('.').next().unwrap_or_default();
+            file_stem == stem || file_stem == lib_stem
+        })
+        .collect();
+    outputs.sort();
+    outputs
+}
+
+// Return the target crates and an hashmap with the dep unit name as key and the dep entry as
+// value, only the entries of the last build are used
+pub fn parse_deps_args(unpanic_dir: &Path) -> Result<Manifest, Error> {
+    let dir = unpanic_dir.join(MANIFEST_DIR);
+    let invalid = |path: &Path, reason: String| Error::InvalidManifest {
+        path: path.to_path_buf(),
+        reason,
+    };
+    let build_path = unpanic_dir.join(BUILD_FILE);
+    let build = std::fs::read_to_string(&build_path).map_err(|e| {
+        invalid(
+            &build_path,
+            format!("{}, was it built with cargo unpanic?", e),
+        )
+    })?;
+    let build: BuildRecord =
+        serde_json::from_str(&build).map_err(|e| invalid(&build_path, e.to_string()))?;
+    let mut paths: Vec<PathBuf> = std::fs::read_dir(&dir)
+        .map_err(|e| {
+            invalid(
+                &dir,
+                format!(
+                    "{}, were the crates built with unpanic as RUSTC_WRAPPER?",
+                    e
+                ),
+            )
+        })?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().map_or(false, |ext| ext == "json"))
+        .collect();
+    // Always parse the entries in the same order, the targets are analyzed in this order
+    paths.sort();
+    let mut entries = vec![];
+    for path in paths {
+        let content = std::fs::read_to_string(&path).map_err(|e| invalid(&path, e.to_string()))?;
+        match parse_entry(&path, &content) {
+            Ok(entry) => entries.push(entry),
+            // An entry of a previous build written by another version of unpanic, eg of a crate
+            // that is no longer a dependency. Only the entries of this build must be up to date.
+            Err(Error::ManifestVersionMismatch { .. })
+                if !serde_json::from_str(&content).map_or(true, |entry| build.uses(&entry)) =>
+            {
+                warn!(
+                    "ignoring {}, it has been written by another version of unpanic and it is not \
+                     used by this build",
+                    path.display()
+                );
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    let manifest = select_entries(&build, entries)?;
+    if manifest.targets.is_empty() {
+        return Err(invalid(&dir, "no target crate".to_string()));
     }
-    crate_name.push(' ');
-    let path = get_unpanic_path(&args, path_index).expect("ERROR: No unpanic path");
-    let path = std::path::Path::new(&path);
-    let serialized = serialize_args(args);
-    crate_name.push_str(serialized.as_str());
-    let row = crate_name;
-    let parent_dir = path.parent().expect("ERROR: No parent dir");
-    std::fs::create_dir_all(parent_dir).expect("ERROR: Impossible to create directory");
-    let mut file = OpenOptions::new()
-        .append(true)
-        .open(path)
-        .unwrap_or_else(|_| std::fs::File::create(path).unwrap());
-    writeln!(file, "{}", row).expect("ERROR: Can not write to file");
-}
-
-pub fn serialize_args(args: Vec<String>) -> String {
-    let mut x = format!("{:?}", args);
-    x.remove_matches("[");
-    x.remove_matches("]");
-    x.remove_matches('"');
-    x.remove_matches(",");
-    x
-}
-
-// Return an hashmap with the dep name as key and the rustc args for that dep as args
-pub fn parse_deps_args(
-    args: &[String],
-    index: Option<usize>,
-) -> std::collections::HashMap<String, (/* build.rs */ Option<Vec<String>>, Vec<String>)> {
-    let path = match index {
-        Some(i) => get_unpanic_path(args, i).expect("ERROR MESSAGE"),
-        None => "./target/no-panic/deps".to_string(),
+    Ok(manifest)
+}
+
+/// The entries of a previous build (eg before `Cargo.toml` or the features changed) are ignored.
+/// Every artifact of the build must have an entry, otherwise the unit has been compiled without
+/// the wrapper and can not be analyzed.
+fn select_entries(build: &BuildRecord, entries: Vec<CrateEntry>) -> Result<Manifest, Error> {
+    let is_build_script = |artifact: &Path| {
+        artifact.file_name().map_or(false, |name| {
+            let name = name.to_string_lossy();
+            name.starts_with("build_script_build") || name.starts_with("build-script-build")
+        })
     };
-    let mut dep_map = std::collections::HashMap::new();
-    // At this point this file must exist
-    for line_ in
-        std::io::BufReader::new(std::fs::File::open(path.clone()).unwrap_or(
-            std::fs::File::open("/dev/null").expect("ERROR: Impossible to open dev null"),
-        ))
-        .lines()
-    {
-        let line_ = line_.expect("ERROR: No lines in deps file");
-        if line_ == "" {
+    for artifact in build.artifacts.iter().filter(|a| !is_build_script(a)) {
+        if !entries.iter().any(|entry| entry.produced(artifact)) {
+            return Err(Error::InvalidManifest {
+                path: artifact.clone(),
+                reason: "it was not built with unpanic as RUSTC_WRAPPER, remove it with `cargo \
+                         clean` and run the analysis again"
+                    .to_string(),
+            });
+        }
+    }
+    let mut manifest = Manifest::default();
+    for entry in entries {
+        if !build
+            .artifacts
+            .iter()
+            .any(|artifact| entry.produced(artifact))
+        {
             continue;
         }
-        let mut line = line_.split_whitespace().map(|s| s.to_string());
-        dep_map.insert(
-            line.next().expect(format!("ERROR: Invalid deps file format \n {:#?} \n {:?}", args, line_).as_str()),
-            (None, line.collect::<Vec<String>>()),
-        );
+        if build.is_target(&entry) {
+            manifest.targets.push(entry);
+        } else {
+            manifest.deps.insert(entry.unit_name(), entry);
+        }
+    }
+    Ok(manifest)
+}
+
+fn parse_entry(path: &Path, content: &str) -> Result<CrateEntry, Error> {
+    let invalid = |reason: String| Error::InvalidManifest {
+        path: path.to_path_buf(),
+        reason,
+    };
+    // Check the version before the fields, an entry written by another version of unpanic can
+    // have different fields
+    let value: serde_json::Value =
+        serde_json::from_str(content).map_err(|e| invalid(e.to_string()))?;
+    let version = value.get("version").and_then(|version| version.as_u64());
+    if version != Some(MANIFEST_VERSION) {
+        return Err(Error::ManifestVersionMismatch {
+            path: path.to_path_buf(),
+            found: version,
+            expected: MANIFEST_VERSION,
+        });
     }
-    let _ = std::fs::remove_file(path);
-    dep_map
+    serde_json::from_value(value).map_err(|e| invalid(e.to_string()))
+}
+
+#[test]
+fn test_parse_manifest() {
+    let unpanic_dir = std::env::temp_dir().join(format!("unpanic-manifest-{}", std::process::id()));
+    let dir = unpanic_dir.join(MANIFEST_DIR);
+    let out_dir = unpanic_dir.join("debug");
+    std::fs::create_dir_all(out_dir.join("deps")).unwrap();
+    let mut entry = CrateEntry {
+        version: MANIFEST_VERSION,
+        crate_name: "test1_bin".to_string(),
+        package_id: None,
+        package_version: None,
+        rustc_version: "1.74.0-nightly (58eefc33a 2023-08-24)".to_string(),
+        cwd: PathBuf::from("/ws"),
+        args: vec![
+            "--cfg".to_string(),
+            "feature=\"a b\"".to_string(),
+            "-C".to_string(),
+            "extra-filename=-53b4ac4b".to_string(),
+        ],
+        env: [("CARGO_MANIFEST_DIR", "/ws/test1_bin")]
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect(),
+        outputs: vec![out_dir.join("deps/test1_bin-53b4ac4b")],
+    };
+    // The binary of a selected package is uplifted with the name of the bin target
+    std::fs::write(&entry.outputs[0], "bin").unwrap();
+    std::fs::hard_link(&entry.outputs[0], out_dir.join("test1-bin")).unwrap();
+    std::fs::copy(&entry.outputs[0], out_dir.join("copy")).unwrap();
+    assert!(!same_file(&entry.outputs[0], &out_dir.join("copy")));
+    write_entry(&dir, &entry).unwrap();
+    let target = serde_json::to_string(&entry).unwrap();
+    entry.crate_name = "test1_lib".to_string();
+    entry.env = [
+        ("CARGO_MANIFEST_DIR", "/ws/test1_lib"),
+        ("CARGO_PKG_NAME", "test1-lib"),
+        ("CARGO_PKG_VERSION", "0.1.0"),
+    ]
+    .iter()
+    .map(|(k, v)| (k.to_string(), v.to_string()))
+    .collect();
+    entry.package_id = package_id(&entry.env);
+    entry.outputs = vec![out_dir.join("deps/libtest1_lib-53b4ac4b.rlib")];
+    write_entry(&dir, &entry).unwrap();
+    assert!(dir.join("test1_lib-53b4ac4b.json").exists());
+    // A crate of the same name from another package is another unit
+    let mut other_unit = entry.clone();
+    other_unit.args[3] = "extra-filename=-9c1d2e3f".to_string();
+    other_unit.env.insert(
+        "CARGO_MANIFEST_DIR".to_string(),
+        "/registry/test1-lib-0.2.0".to_string(),
+    );
+    other_unit
+        .env
+        .insert("CARGO_PKG_VERSION".to_string(), "0.2.0".to_string());
+    other_unit.package_id = package_id(&other_unit.env);
+    other_unit.outputs = vec![out_dir.join("deps/libtest1_lib-9c1d2e3f.rlib")];
+    write_entry(&dir, &other_unit).unwrap();
+    let mut stale = entry.clone();
+    stale.crate_name = "stale".to_string();
+    stale.outputs = vec![out_dir.join("deps/libstale-53b4ac4b.rlib")];
+    write_entry(&dir, &stale).unwrap();
+    let build = serde_json::json!({
+        "targets": ["/ws/test1_bin"],
+        "artifacts": [
+            out_dir.join("test1-bin"),
+            out_dir.join("deps/libtest1_lib-53b4ac4b.rlib"),
+            out_dir.join("deps/libtest1_lib-9c1d2e3f.rlib"),
+        ],
+    });
+    std::fs::write(unpanic_dir.join(BUILD_FILE), build.to_string()).unwrap();
+    let manifest = parse_deps_args(&unpanic_dir).unwrap();
+    assert_eq!(manifest.targets[0].args[1], "feature=\"a b\"");
+    assert_eq!(manifest.deps["test1_lib-53b4ac4b"], entry);
+    assert_eq!(manifest.deps["test1_lib-9c1d2e3f"], other_unit);
+    assert!(!manifest.deps.contains_key("stale-53b4ac4b"));
+    assert_eq!(manifest.targets[0].package_id, None);
+    assert_eq!(
+        manifest.deps["test1_lib-53b4ac4b"].package_id.as_deref(),
+        Some("path+file:///ws/test1_lib#test1-lib@0.1.0")
+    );
+    assert_eq!(
+        manifest.deps["test1_lib-9c1d2e3f"].package_id.as_deref(),
+        Some("path+file:///registry/test1-lib-0.2.0#test1-lib@0.2.0")
+    );
+    assert_eq!(unit_crate_name("test1_lib-9c1d2e3f"), "test1_lib");
+    // The entries are kept for the next analysis
+    assert!(parse_deps_args(&unpanic_dir).is_ok());
+    // An entry of another version of unpanic is an error only if it is used by the build
+    let old_version = |entry: &CrateEntry| {
+        serde_json::to_string(entry).unwrap().replace(
+            &format!("\"version\":{}", MANIFEST_VERSION),
+            &format!("\"version\":{}", MANIFEST_VERSION - 1),
+        )
+    };
+    std::fs::write(dir.join("stale-53b4ac4b.json"), old_version(&stale)).unwrap();
+    assert!(parse_deps_args(&unpanic_dir).is_ok());
+    std::fs::write(
+        dir.join("test1_lib-9c1d2e3f.json"),
+        old_version(&other_unit),
+    )
+    .unwrap();
+    assert!(matches!(
+        parse_deps_args(&unpanic_dir),
+        Err(Error::ManifestVersionMismatch { .. })
+    ));
+    write_entry(&dir, &other_unit).unwrap();
+    std::fs::remove_file(dir.join("test1_lib-53b4ac4b.json")).unwrap();
+    assert!(matches!(
+        parse_deps_args(&unpanic_dir),
+        Err(Error::InvalidManifest { .. })
+    ));
+    let _ = std::fs::remove_dir_all(unpanic_dir);
+
+    let path = Path::new("test1_bin.json");
+    let old_format = "test1_lib --crate-name test1_lib";
+    assert!(matches!(
+        parse_entry(path, old_format),
+        Err(Error::InvalidManifest { .. })
+    ));
+    let next_version = target.replace("\"version\":5", "\"version\":6");
+    assert!(matches!(
+        parse_entry(path, &next_version),
+        Err(Error::ManifestVersionMismatch { found: Some(6), .. })
+    ));
 }
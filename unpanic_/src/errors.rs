@@ -1,7 +1,206 @@
+use std::path::PathBuf;
+
 #[derive(Debug)]
 pub enum Error {
+    // Arguments
     InvalidOutDir,
     CrateNameMissing,
     TargetPathMissing,
-    SrcLocationMissing,
+    MissingArgument(String),
+    InvalidArgument(String),
+    InvalidRustcArgs {
+        crate_name: String,
+        reason: String,
+    },
+    UnsupportedEdition(String),
+    MultipleEditions(Vec<String>),
+    InvalidConfig(String),
+    // Manifest
+    InvalidManifest {
+        path: PathBuf,
+        reason: String,
+    },
+    ManifestVersionMismatch {
+        path: PathBuf,
+        found: Option<u64>,
+        expected: u64,
+    },
+    CannotWriteManifest {
+        path: PathBuf,
+        reason: String,
+    },
+    CrateNotRecorded(String),
+    // Baseline
+    InvalidBaseline {
+        path: PathBuf,
+        reason: String,
+    },
+    CannotWriteBaseline {
+        path: PathBuf,
+        reason: String,
+    },
+    // Extern resolution
+    UnresolvedCall {
+        crate_name: String,
+        callee: String,
+        reason: String,
+    },
+    // Compiler session
+    InvalidSysroot(String),
+    RustcVersionMismatch {
+        what: String,
+        found: String,
+        expected: String,
+    },
+    CannotRunWrapper {
+        wrapper: String,
+        reason: String,
+    },
+    CompilerSession {
+        crate_name: String,
+        reason: String,
+    },
+    // Analysis
+    Analysis {
+        crate_name: String,
+        location: Option<String>,
+        reason: String,
+    },
+    CannotWriteReport {
+        path: Option<PathBuf>,
+        reason: String,
+    },
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::InvalidOutDir => write!(
+                f,
+                "invalid --out-dir in rustc args: set UNPANIC_DIR to the directory where the \
+                 manifest must be written"
+            ),
+            Error::CrateNameMissing => write!(f, "no --crate-name in rustc args"),
+            Error::TargetPathMissing => write!(f, "no --out-dir in rustc args"),
+            Error::MissingArgument(arg) => write!(f, "missing argument {}", arg),
+            Error::InvalidArgument(reason) => write!(f, "invalid argument: {}", reason),
+            Error::InvalidRustcArgs { crate_name, reason } => {
+                write!(f, "invalid rustc args for {}: {}", crate_name, reason)
+            }
+            Error::UnsupportedEdition(edition) => write!(f, "unsupported edition {}", edition),
+            Error::MultipleEditions(editions) => {
+                write!(f, "more editions in rustc args: {}", editions.join(", "))
+            }
+            Error::InvalidConfig(reason) => write!(f, "invalid config {}", reason),
+            Error::InvalidManifest { path, reason } => {
+                write!(f, "invalid manifest {}: {}", path.display(), reason)
+            }
+            Error::ManifestVersionMismatch {
+                path,
+                found,
+                expected,
+            } => write!(
+                f,
+                "manifest {} has version {}, expected {}: rebuild with `cargo clean` and then \
+                 `cargo unpanic`",
+                path.display(),
+                found.map_or("none".to_string(), |found| found.to_string()),
+                expected
+            ),
+            Error::CannotWriteManifest { path, reason } => write!(
+                f,
+                "can not write the manifest entry in {}: {}",
+                path.display(),
+                reason
+            ),
+            Error::CrateNotRecorded(crate_name) => write!(
+                f,
+                "the crate {} is not in the manifest: rebuild with `cargo unpanic`",
+                crate_name
+            ),
+            Error::InvalidBaseline { path, reason } => {
+                write!(f, "invalid baseline {}: {}", path.display(), reason)
+            }
+            Error::CannotWriteBaseline { path, reason } => write!(
+                f,
+                "can not write the baseline in {}: {}",
+                path.display(),
+                reason
+            ),
+            Error::UnresolvedCall {
+                crate_name,
+                callee,
+                reason,
+            } => write!(
+                f,
+                "can not resolve the call to `{}` in {}: {}",
+                callee, crate_name, reason
+            ),
+            Error::InvalidSysroot(reason) => write!(f, "can not find the sysroot: {}", reason),
+            Error::RustcVersionMismatch {
+                what,
+                found,
+                expected,
+            } => write!(
+                f,
+                "{} is from rustc {} but unpanic is built with rustc {}, the metadata of one \
+                 can not be read by the other: use the toolchain unpanic is built with (check \
+                 RUSTUP_TOOLCHAIN, RUSTC, SYSROOT and rust-toolchain.toml)",
+                what, found, expected
+            ),
+            Error::CannotRunWrapper { wrapper, reason } => {
+                write!(f, "can not run the wrapper {}: {}", wrapper, reason)
+            }
+            Error::CompilerSession { crate_name, reason } => {
+                write!(f, "rustc can not analyze {}: {}", crate_name, reason)
+            }
+            Error::Analysis {
+                crate_name,
+                location,
+                reason,
+            } => {
+                write!(f, "can not analyze {}", crate_name)?;
+                if let Some(location) = location {
+                    write!(f, " at {}", location)?;
+                }
+                write!(
+                    f,
+                    ": {}, this is a bug in unpanic please report it with the code that can not \
+                     be analyzed",
+                    reason
+                )
+            }
+            Error::CannotWriteReport { path, reason } => match path {
+                Some(path) => write!(
+                    f,
+                    "can not write the report in {}: {}",
+                    path.display(),
+                    reason
+                ),
+                None => write!(f, "can not write the report: {}", reason),
+            },
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+#[test]
+fn test_display() {
+    let error = Error::Analysis {
+        crate_name: "test1_lib".to_string(),
+        location: Some("src/lib.rs:3:5: 3:12".to_string()),
+        reason: "empty path".to_string(),
+    };
+    assert!(error.to_string().starts_with(
+        "can not analyze test1_lib at src/lib.rs:3:5: 3:12: empty path, this is a bug"
+    ));
+    let error = Error::CompilerSession {
+        crate_name: "test1_lib".to_string(),
+        reason: "see the errors above".to_string(),
+    };
+    assert_eq!(
+        error.to_string(),
+        "rustc can not analyze test1_lib: see the errors above"
+    );
 }
@@ -0,0 +1,116 @@
+//! Build the frames of the call stacks. The spans and the def ids are resolved when the frame is
+//! built, cause they have no meaning outside of the rustc session in which they have been found.
+use rustc_hir::def_id::{DefId, LOCAL_CRATE};
+use rustc_middle::ty::print::with_no_trimmed_paths;
+use rustc_middle::ty::TyCtxt;
+use rustc_span::{Pos, Span};
+
+use crate::report::{CallFrame, CallKind, Location};
+
+/// The function that contains the 'deny_panic block, span is the span of the block
+pub fn deny_block(tcx: TyCtxt<'_>, function: DefId, span: Span) -> CallFrame {
+    CallFrame {
+        path: def_path(tcx, function),
+        crate_name: tcx.crate_name(function.krate).to_string(),
+        crate_version: None,
+        kind: CallKind::DenyBlock,
+        via_trait: None,
+        caller_span: None,
+        callee_span: Some(location(tcx, span)),
+    }
+}
+
+/// A call to function in call_site
+pub fn call(tcx: TyCtxt<'_>, function: DefId, kind: CallKind, call_site: Span) -> CallFrame {
+    CallFrame {
+        path: def_path(tcx, function),
+        crate_name: tcx.crate_name(function.krate).to_string(),
+        crate_version: None,
+        kind,
+        via_trait: None,
+        caller_span: Some(location(tcx, call_site)),
+        callee_span: Some(location(tcx, tcx.def_span(function))),
+    }
+}
+
+/// A call to trait_fn in call_site that has been resolved to its implementation impl_fn
+pub fn trait_impl_call(
+    tcx: TyCtxt<'_>,
+    impl_fn: DefId,
+    trait_fn: DefId,
+    call_site: Span,
+) -> CallFrame {
+    CallFrame {
+        via_trait: Some(def_path(tcx, trait_fn)),
+        ..call(tcx, impl_fn, CallKind::TraitImpl, call_site)
+    }
+}
+
+/// frame is a call to a trait method that has been resolved to its implementation impl_fn in a
+/// later analysis of the crate that implements it
+pub fn resolve_trait_call(tcx: TyCtxt<'_>, frame: &mut CallFrame, impl_fn: DefId) {
+    frame.via_trait = Some(frame.path.clone());
+    frame.path = def_path(tcx, impl_fn);
+    frame.kind = CallKind::TraitImpl;
+    frame.callee_span = Some(location(tcx, tcx.def_span(impl_fn)));
+}
+
+/// The 'allow_panic block in function
+pub fn allow_block(tcx: TyCtxt<'_>, function: DefId, block: Span) -> CallFrame {
+    CallFrame {
+        kind: CallKind::AllowBlock,
+        caller_span: None,
+        callee_span: Some(location(tcx, block)),
+        ..deny_block(tcx, function, block)
+    }
+}
+
+/// The argument passed in arg_span to function, where it is called as trait_fn (eg
+/// `core::ops::Fn::call` for a closure)
+pub fn higher_order_argument(
+    tcx: TyCtxt<'_>,
+    function: DefId,
+    trait_fn: DefId,
+    arg_span: Span,
+) -> CallFrame {
+    CallFrame {
+        via_trait: Some(def_path(tcx, trait_fn)),
+        ..call(tcx, function, CallKind::HigherOrderArgument, arg_span)
+    }
+}
+
+/// Fully qualified path of def_id, the methods are prefixed by the path of their type, eg
+/// `test1_lib::method_test::MethodTest::method_test` and not
+/// `<method_test::MethodTest as Trait>::method_test`
+pub fn def_path(tcx: TyCtxt<'_>, def_id: DefId) -> String {
+    if let Some(impl_id) = tcx.impl_of_method(def_id) {
+        if let Some(adt_def) = tcx.type_of(impl_id).skip_binder().ty_adt_def() {
+            return format!(
+                "{}::{}",
+                def_path(tcx, adt_def.did()),
+                tcx.item_name(def_id)
+            );
+        }
+    }
+    // Without with_no_trimmed_paths rustc expects a diagnostic to be emitted
+    let path = with_no_trimmed_paths!(tcx.def_path_str(def_id));
+    if def_id.is_local() && !path.starts_with('<') {
+        format!("{}::{}", tcx.crate_name(LOCAL_CRATE), path)
+    } else {
+        path
+    }
+}
+
+pub fn location(tcx: TyCtxt<'_>, span: Span) -> Location {
+    let source_map = tcx.sess.source_map();
+    let lo = source_map.lookup_char_pos(span.lo());
+    let hi = source_map.lookup_char_pos(span.hi());
+    Location {
+        file: lo.file.name.prefer_local().to_string(),
+        line: lo.line,
+        // rustc columns are 0-based
+        column: lo.col.to_usize() + 1,
+        end_line: hi.line,
+        end_column: hi.col.to_usize() + 1,
+    }
+}
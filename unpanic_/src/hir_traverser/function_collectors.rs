@@ -1,21 +1,21 @@
 //! helpers to get BodyId of specific fucntions in a crate.
 
+use super::call_frames;
 use super::traversers::FunctionCallPartialTree;
-use crate::utils::log_allow_panic;
-use rustc_hir::def_id::{LOCAL_CRATE,DefIndex};
+use crate::errors::Error;
+use crate::report::CallFrame;
+use rustc_hir::def_id::{LOCAL_CRATE,DefPathHash};
 use rustc_hir::{
-    def_id::DefId, Block, BodyId, Expr, ExprKind, GenericBound, GenericParamKind, HirId, Item,
-    ItemKind, Node, QPath, StmtKind, TraitFn,def::Res,ImplItemKind,
+    def_id::DefId, Block, BodyId, Expr, ExprKind, HirId, Item,
+    ItemKind, Node, QPath, StmtKind, TraitFn,def::Res,ImplItem,ImplItemKind,TraitItem,TraitItemKind,
 };
-use rustc_middle::hir::map::Map;
-use rustc_span::Span;
 use std::collections::HashMap;
-use std::collections::HashSet;
 use rustc_middle::ty::fast_reject::{TreatParams,simplify_type};
+use rustc_middle::ty::{AliasKind, Ty};
 use rustc_type_ir::sty::TyKind;
 //use super::traversers::get_call_in_block;
 
-pub fn get_all_fn_in_crate<'tcx>(tcx: &mut TyCtxt<'tcx>) -> Vec<Block<'tcx>> {
+pub fn get_all_fn_in_crate<'tcx>(tcx: &TyCtxt<'tcx>) -> Vec<Block<'tcx>> {
     let mut ret = vec![];
     let hir_krate = tcx.hir();
     for item_id in hir_krate.items() {
@@ -28,19 +28,11 @@ pub fn get_all_fn_in_crate<'tcx>(tcx: &mut TyCtxt<'tcx>) -> Vec<Block<'tcx>> {
             }
             rustc_hir::ItemKind::Impl(impl_) => {
                 for item in impl_.items {
-                    let hir = item.id.hir_id();
-                    match hir_krate.get(hir) {
-                        Node::ImplItem(item) => match item.kind {
-                            rustc_hir::ImplItemKind::Fn(_, body_id) => {
-                                if let ExprKind::Block(block, _) =
-                                    hir_krate.body(body_id).value.kind
-                                {
-                                    ret.push(*block);
-                                }
-                            }
-                            _ => (),
-                        },
-                        _ => panic!("Expected impl item {:#?}", item),
+                    let item = hir_krate.impl_item(item.id);
+                    if let rustc_hir::ImplItemKind::Fn(_, body_id) = item.kind {
+                        if let ExprKind::Block(block, _) = hir_krate.body(body_id).value.kind {
+                            ret.push(*block);
+                        }
                     }
                 }
             }
@@ -50,22 +42,22 @@ pub fn get_all_fn_in_crate<'tcx>(tcx: &mut TyCtxt<'tcx>) -> Vec<Block<'tcx>> {
     ret
 }
 
-/// Traverse an HIR and for each function that contains a block labelled 'deny_panic return a
+/// Traverse an HIR and for each block labelled 'deny_panic return a
 /// a (BodyId, (deny_panic_blocks, call_stack)) where:
-///     * BodyId is the BodyId of the function
-///     * deny_panic_blocks are all the block in the function body labelled 'deny_panic
-///     * call satck is a vector that cointains the path of all the function in the call stack for
-///     logging purposes. In that case it will contains only the path of the function itself,
+///     * BodyId is the BodyId of the function that contains the block
+///     * deny_panic_blocks is the block
+///     * call satck is a vector that cointains the frames of all the function in the call stack
+///     for logging purposes. In that case it will contains only the frame of the block itself,
 ///     cause `get_functions` is called on the target crate.
 #[allow(clippy::type_complexity)]
 pub fn get_functions<'tcx>(
-    tcx: &mut TyCtxt<'tcx>,
+    tcx: &TyCtxt<'tcx>,
 ) -> Vec<(
     BodyId,
-    (Vec<&'tcx Block<'tcx>>, /* call_stack */ Vec<String>),
+    (Vec<&'tcx Block<'tcx>>, /* call_stack */ Vec<CallFrame>),
 )> {
     let mut ret = vec![];
-    let mut hir_krate = tcx.hir();
+    let hir_krate = tcx.hir();
     for item_id in hir_krate.items() {
         let item = hir_krate.item(item_id);
         match item.kind {
@@ -73,29 +65,24 @@ pub fn get_functions<'tcx>(
                 let mut deny_panic_blocks = vec![];
                 let expr = hir_krate.body(body_id).value;
                 get_deny_panic_in_expr(expr, &mut deny_panic_blocks);
-                if !deny_panic_blocks.is_empty() {
-                    let function = format!("{} in {:?}", item.ident.to_string(), item.span);
-                    ret.push((body_id, (deny_panic_blocks, vec![function])));
+                for block in deny_panic_blocks {
+                    let function =
+                        call_frames::deny_block(*tcx, item.owner_id.to_def_id(), block.span);
+                    ret.push((body_id, (vec![block], vec![function])));
                 }
             }
             rustc_hir::ItemKind::Impl(impl_) => {
                 for item in impl_.items {
-                    let hir = item.id.hir_id();
-                    match hir_krate.get(hir) {
-                        Node::ImplItem(item) => match item.kind {
-                            rustc_hir::ImplItemKind::Fn(_, body_id) => {
-                                let mut deny_panic_blocks = vec![];
-                                let expr = hir_krate.body(body_id).value;
-                                get_deny_panic_in_expr(expr, &mut deny_panic_blocks);
-                                if !deny_panic_blocks.is_empty() {
-                                    let function =
-                                        format!("{} in {:?}", item.ident.to_string(), item.span);
-                                    ret.push((body_id, (deny_panic_blocks, vec![function])));
-                                }
-                            }
-                            _ => (),
-                        },
-                        _ => panic!("Expected impl item {:#?}", item),
+                    let item = hir_krate.impl_item(item.id);
+                    if let rustc_hir::ImplItemKind::Fn(_, body_id) = item.kind {
+                        let mut deny_panic_blocks = vec![];
+                        let expr = hir_krate.body(body_id).value;
+                        get_deny_panic_in_expr(expr, &mut deny_panic_blocks);
+                        for block in deny_panic_blocks {
+                            let function =
+                                call_frames::deny_block(*tcx, item.owner_id.to_def_id(), block.span);
+                            ret.push((body_id, (vec![block], vec![function])));
+                        }
                     }
                 }
             }
@@ -105,31 +92,30 @@ pub fn get_functions<'tcx>(
     ret
 }
 
+/// The calls to the functions with procedural parameters, by caller block: the call, the called
+/// function, the parameter numbers with their trait method and the caller frame
+type Callers<'tcx> = HashMap<HirId, Vec<(Expr<'tcx>, DefId, HashMap<usize,DefId>,CallFrame)>>;
+
 /// For each expr in callers check if it contaion call to function in called;
+/// called has been collected in the sessions of other crates, its def paths are resolved to the
+/// def ids of this session.
 pub fn get_callers<'tcx>(
-    tcx: &mut TyCtxt<'tcx>,
+    tcx: &TyCtxt<'tcx>,
     callers: Vec<Block<'tcx>>,
-    called: HashMap<DefId, HashMap<usize,DefId>>,
-) -> HashMap<HirId, Vec<(Expr<'tcx>, DefId, HashMap<usize,DefId>,String)>> {
-    let mut ret: HashMap<HirId, Vec<(Expr<'tcx>, DefId, HashMap<usize,DefId>,String)>> = HashMap::new();
-    // TODO is DefIndex correct here?
-    let called: HashMap<DefIndex, HashMap<usize,DefId>> = called.into_iter().map(|(def_id, v)| {
-        (def_id.index, v.clone())
-    }).collect();
+    called: HashMap<DefPathHash, HashMap<usize,DefPathHash>>,
+) -> Callers<'tcx> {
+    let mut ret: Callers<'tcx> = HashMap::new();
     for block in callers {
-        let to_log = format!("{:?} in {:?}", block.hir_id.owner, block.span);
+        let to_log = call_frames::deny_block(*tcx, block.hir_id.owner.to_def_id(), block.span);
         let mut traverser = FunctionCallPartialTree::new(*tcx, false);
         traverser.traverse_block(&block, &mut vec![]);
         for call in traverser.first_level_calls {
             if let Some(def_id) = from_callers_to_called_def_id(tcx,call) {
-                if called.contains_key(&def_id.index) {
-                    if let Some(v) = ret.get_mut(&block.hir_id) {
-                        let map = called.get(&def_id.index).unwrap().clone();
-                        v.push((call, def_id, map, to_log.clone()));
-                    } else {
-                        let map = called.get(&def_id.index).unwrap().clone();
-                        ret.insert(block.hir_id, vec![(call, def_id, map,to_log.clone())]);
-                    }
+                if let Some(map) = called.get(&tcx.def_path_hash(def_id)) {
+                    let map = map.iter()
+                        .filter_map(|(i, trait_fn)| Some((*i, def_id_from_hash(*tcx, *trait_fn)?)))
+                        .collect();
+                    ret.entry(block.hir_id).or_default().push((call, def_id, map, to_log.clone()));
                 }
             }
         }
@@ -137,32 +123,49 @@ pub fn get_callers<'tcx>(
     ret
 }
 
+/// The def id of the trait method hash in the session of tcx, None if its crate is not loaded. The
+/// def ids can not be shared between sessions, the crate numbers depend on the crates loaded by
+/// each session.
+fn def_id_from_hash(tcx: TyCtxt<'_>, hash: DefPathHash) -> Option<DefId> {
+    let krate = tcx.crates(()).iter().copied().chain(Some(LOCAL_CRATE))
+        .find(|krate| tcx.stable_crate_id(*krate) == hash.stable_crate_id())?;
+    // The trait aliases have no items
+    tcx.traits(krate).iter()
+        .filter(|trait_id| tcx.is_trait(**trait_id))
+        .flat_map(|trait_id| tcx.associated_item_def_ids(*trait_id))
+        .copied()
+        .find(|def_id| tcx.def_path_hash(*def_id) == hash)
+}
+
 /// Given an HashMap of callers DefId -> (calling_expr, DefId, deny args)
-/// return the Expr rapresenting the denied arg in the calling expression
-pub fn callers_into_args<'tcx>(callers: HashMap<HirId, Vec<(Expr<'tcx>, DefId, HashMap<usize,DefId>,String)>>) -> Vec<(Expr<'tcx>,String,DefId)> {
+/// return the Expr rapresenting the denied arg in the calling expression, with the call stack
+/// that goes from the caller to the arg
+pub fn callers_into_args<'tcx>(tcx: &TyCtxt<'tcx>, callers: Callers<'tcx>) -> Vec<(Expr<'tcx>,Vec<CallFrame>,DefId)> {
     let mut ret = vec![];
     let mut control = vec![];
     callers.values().for_each(|v| {
-        v.iter().for_each(|(expr,_,arg_indexes,to_log)| {
+        v.iter().for_each(|(expr,called,arg_indexes,to_log)| {
+            let stack = |arg: &Expr<'tcx>, trait_fn: DefId| {
+                vec![
+                    to_log.clone(),
+                    call_frames::higher_order_argument(*tcx, *called, trait_fn, arg.span),
+                ]
+            };
             let args = match expr.kind {
                 ExprKind::Call(_, args) => args,
                 ExprKind::MethodCall(_, _, args, _) => args,
-                _ => panic!(),
+                // get_callers collects only calls
+                _ => return,
             };
             for (i,def_id) in arg_indexes {
                 // TODO add comment (why arg can be either i or i - 1 ??)
-                if i < &args.len() {
-                    let arg = args[*i];
-                    if ! control.contains(&arg.hir_id) {
-                        control.push(arg.hir_id);
-                        ret.push((arg,to_log.clone(),def_id.clone()));
-                    }
-                } else if i > &0_usize {
-                    let arg = args[*i - 1];
-                    if ! control.contains(&arg.hir_id) {
-                        control.push(arg.hir_id);
-                        ret.push((arg,to_log.clone(),def_id.clone()));
-                    }
+                let arg = match args.get(*i).or_else(|| i.checked_sub(1).and_then(|i| args.get(i))) {
+                    Some(arg) => *arg,
+                    None => continue,
+                };
+                if ! control.contains(&arg.hir_id) {
+                    control.push(arg.hir_id);
+                    ret.push((arg,stack(&arg,*def_id),*def_id));
                 }
             }
         });
@@ -177,56 +180,78 @@ use rustc_middle::ty::TyCtxt;
 /// call_stack.
 /// If the Block is not labelled 'allow_panic add the BodyId the Block and the call_stack to a
 /// vector then we will return.
-/// If the Block is labelled 'allow_panic log it and continue.
+/// If the Block is labelled 'allow_panic add the call_stack to allow_panics and continue.
+/// Fails if a function can not be found in the crate.
 #[allow(clippy::type_complexity)]
 pub fn get_function_for_dependency<'tcx>(
-    tcx: &mut TyCtxt<'tcx>,
-    ids: Vec<(DefId, /* call_stack */ Vec<String>, Option<DefId>)>,
-) -> Vec<(
-    BodyId,
-    (Vec<&'tcx Block<'tcx>>, /* call_stack */ Vec<String>),
-)> {
+    tcx: &TyCtxt<'tcx>,
+    ids: Vec<(DefId, /* call_stack */ Vec<CallFrame>, Option<DefId>)>,
+    allow_panics: &mut Vec<Vec<CallFrame>>,
+) -> Result<
+    Vec<(
+        BodyId,
+        (Vec<&'tcx Block<'tcx>>, /* call_stack */ Vec<CallFrame>),
+    )>,
+    Error,
+> {
     let hir_krate = tcx.hir();
+    let crate_name = tcx.crate_name(LOCAL_CRATE).to_string();
+    // The last frame of the stack is the call to resolve
+    let unresolved = |stack: &[CallFrame], reason: &str| Error::UnresolvedCall {
+        crate_name: crate_name.clone(),
+        callee: stack.last().map(|call| call.path.clone()).unwrap_or_default(),
+        reason: reason.to_string(),
+    };
     let mut ret = vec![];
     for id_ in ids {
-        let stack = id_.1;
+        let mut stack = id_.1;
         let mut id = id_.0;
         id.krate = LOCAL_CRATE;
-        let item = hir_krate.get_if_local(id).expect("ERROR MESSAGE");
-        let fn_body_id = match item {
-            Node::Item(item) => item.expect_fn().2,
-            Node::ImplItem(item) => item.expect_fn().1,
-            Node::TraitItem(item) => match item.expect_fn().1 {
+        let fn_body_id = match hir_krate.get_if_local(id) {
+            Some(Node::Item(Item { kind: ItemKind::Fn(_, _, body_id), .. })) => *body_id,
+            Some(Node::ImplItem(ImplItem { kind: ImplItemKind::Fn(_, body_id), .. })) => *body_id,
+            Some(Node::TraitItem(TraitItem { kind: TraitItemKind::Fn(_, trait_fn), .. })) => match trait_fn {
                 TraitFn::Provided(body_id) => *body_id,
                 TraitFn::Required(_) => {
-                    let mut receiver = id_.2.expect("Expect receiver");
+                    let mut receiver = id_.2
+                        .ok_or_else(|| unresolved(&stack, "the receiver of the trait method is unknown"))?;
                     receiver.krate = LOCAL_CRATE;
                     let ty = tcx.type_of(receiver).skip_binder();
                     let impl_item = super::get_impl_item(tcx, id, Some(ty))
-                        .expect("Trait is never implemented in crate");
-                    if let Some(Node::ImplItem(item)) = hir_krate.get_if_local(impl_item.def_id) {
-                        item.expect_fn().1
+                        .ok_or_else(|| unresolved(&stack, "the trait is not implemented for the receiver in the crate"))?;
+                    if let Some(Node::ImplItem(ImplItem { kind: ImplItemKind::Fn(_, body_id), .. })) =
+                        hir_krate.get_if_local(impl_item.def_id)
+                    {
+                        if let Some(call) = stack.last_mut() {
+                            call_frames::resolve_trait_call(*tcx, call, impl_item.def_id);
+                        }
+                        *body_id
                     } else {
-                        panic!("Impossible to find called BodyId");
+                        return Err(unresolved(&stack, "the implementation of the trait method is not in the crate"));
                     }
                 }
             },
-            _ => panic!("Item is not a function {:?}", item),
+            Some(_) => return Err(unresolved(&stack, "it is not a function")),
+            None => return Err(unresolved(&stack, "the function is not in the crate")),
         };
         match hir_krate.body(fn_body_id).value.kind {
             ExprKind::Block(block, Some(label)) => {
                 if label.ident.as_str().contains("allow_panic") {
-                    log_allow_panic(&stack);
+                    let function = hir_krate.body_owner_def_id(fn_body_id).to_def_id();
+                    stack.push(call_frames::allow_block(*tcx, function, block.span));
+                    allow_panics.push(stack);
                     continue;
                 } else {
                     ret.push((fn_body_id, (vec![block], stack)));
                 }
             }
             ExprKind::Block(block, None) => ret.push((fn_body_id, (vec![block], stack))),
-            _ => panic!("Not a block (unreachable)"),
+            _ => {
+                return Err(unresolved(&stack, "the body of the function is not a block (async functions are not supported)"))
+            }
         }
     }
-    ret
+    Ok(ret)
 }
 
 /// Only check for first level block:
@@ -275,17 +300,17 @@ pub fn get_deny_panic_in_expr<'tcx>(expr: &Expr<'tcx>, blocks: &mut Vec<&Block<'
 // the passed closure can panic.
 //
 // If it is a trait it does the same TODO verify it and if is the right thing todo
+#[allow(clippy::type_complexity)]
 pub fn get_procedural_parameters<'tcx>(
-    tcx: &mut TyCtxt<'tcx>,
-    deny_panic_fn_bodies: &Vec<(BodyId, (Vec<&Block<'tcx>>, Vec<String>))>,
+    tcx: &TyCtxt<'tcx>,
+    deny_panic_fn_bodies: &[(BodyId, (Vec<&Block<'tcx>>, Vec<CallFrame>))],
 ) -> 
 HashMap<
-    /* id of the function with procedural parameters or trait */ DefId,
-    /* parameter number with trait method def id */ HashMap<usize, DefId>,
+    /* id of the function with procedural parameters or trait */ DefPathHash,
+    /* parameter number with trait method id */ HashMap<usize, DefPathHash>,
 >
 {
-    let mut hir_krate = tcx.hir();
-    let mut to_check_later: HashMap<DefId, HashMap<usize,DefId>> = HashMap::new();
+    let mut to_check_later: HashMap<DefPathHash, HashMap<usize,DefPathHash>> = HashMap::new();
     for deny_panic_fn in deny_panic_fn_bodies {
         let function_id = deny_panic_fn.0.hir_id.owner;
         let typeck_results = tcx.typeck(function_id);
@@ -294,13 +319,19 @@ HashMap<
             let calls_in_block = get_call_in_block(block, tcx);
             for call in calls_in_block {
                 if let Some(i) = get_arg_number(call, body) {
+                    let param_ty = body.params.get(i).map(|param| typeck_results.pat_ty(param.pat));
+                    if !param_ty.map_or(false, is_procedural) {
+                        continue;
+                    }
                     if let Some(res) = typeck_results.type_dependent_def(call.hir_id) {
-                        if let Some(map) = to_check_later.get_mut(&function_id.to_def_id()) {
-                            map.insert(i,res.1);
+                        let function = tcx.def_path_hash(function_id.to_def_id());
+                        let trait_fn = tcx.def_path_hash(res.1);
+                        if let Some(map) = to_check_later.get_mut(&function) {
+                            map.insert(i,trait_fn);
                         } else {
                             let mut map = HashMap::new();
-                            map.insert(i,res.1);
-                            to_check_later.insert(function_id.to_def_id(), map);
+                            map.insert(i,trait_fn);
+                            to_check_later.insert(function, map);
                         }
                     }
                 }
@@ -310,66 +341,80 @@ HashMap<
     to_check_later
 }
 
-pub fn solve_arg<'tcx>(tcx: &mut TyCtxt<'tcx>, arg: Expr<'tcx>, method: DefId) -> Expr<'tcx> {
+/// Only the calls on generic parameters (`impl Trait`, `dyn Trait` or a type parameter) depend on
+/// the callers, eg `to_string` on a `&str` parameter is resolved in the function itself
+fn is_procedural(ty: Ty<'_>) -> bool {
+    match ty.kind() {
+        TyKind::Param(_) | TyKind::Dynamic(..) | TyKind::Alias(AliasKind::Opaque, _) => true,
+        TyKind::Ref(_, ty, _) => is_procedural(*ty),
+        _ if ty.is_box() => is_procedural(ty.boxed_ty()),
+        _ => false,
+    }
+}
+
+/// The expression that is called when arg is called as method: arg itself if it is a closure,
+/// otherwise the body of the implementation of method for the type of arg
+pub fn solve_arg<'tcx>(
+    tcx: &TyCtxt<'tcx>,
+    arg: Expr<'tcx>,
+    method: DefId,
+) -> Result<Expr<'tcx>, Error> {
+    let unsupported =
+        |reason: &str| super::analysis_error(*tcx, arg.span, &format!("{} passed to a 'deny_panic block", reason));
     match arg.kind {
         ExprKind::Path(path) => match path {
             QPath::Resolved(_,path) => {
-                let last = path.segments.last().unwrap();
+                let last = path.segments.last().ok_or_else(|| unsupported("empty path"))?;
                 match last.res {
                     Res::Local(id) => {
                         let result = tcx.typeck(id.owner.def_id);
                         let ty = result.expr_ty(&arg);
-                        let trait_id = tcx.trait_of_item(method).unwrap();
-                        let mut trait_impls = tcx.trait_impls_of(trait_id);
+                        let trait_id = tcx.trait_of_item(method)
+                            .ok_or_else(|| unsupported("argument that is not called via a trait"))?;
+                        let trait_impls = tcx.trait_impls_of(trait_id);
                         if ! trait_impls.blanket_impls().is_empty() {
-                            todo!()
+                            return Err(unsupported("argument of a trait with blanket implementations"));
                         }
-                        let simplified_ty = simplify_type(tcx.clone(),ty,TreatParams::ForLookup).unwrap();
+                        let simplified_ty = simplify_type(*tcx,ty,TreatParams::ForLookup)
+                            .ok_or_else(|| unsupported("argument of a type that can not be simplified"))?;
                         if let Some(def_ids) = trait_impls.non_blanket_impls().get(&simplified_ty) {
-                            let trait_items = match def_ids.len() {
-                                1 => tcx.associated_items(def_ids[0]),
+                            let trait_items = match def_ids.as_slice() {
+                                [def_id] => tcx.associated_items(*def_id),
                                 // TODO here I should have only one element cause I the impl of
                                 // Trait and then I get the impl for Type so I should always have
                                 // at maximum one element ??
-                                _ => todo!(),
+                                _ => return Err(unsupported("argument with more implementations of the trait")),
                             };
                             for item in trait_items.in_definition_order() {
-                                if item.trait_item_def_id.unwrap() == method {
-                                    let def_id = item.def_id.as_local().unwrap();
-                                    match tcx.hir().get_by_def_id(def_id).expect_impl_item().kind {
-                                        ImplItemKind::Fn(_,body_id) => {
-                                            return tcx.hir().body(body_id).value.clone()
+                                if item.trait_item_def_id == Some(method) {
+                                    let def_id = item.def_id.as_local()
+                                        .ok_or_else(|| unsupported("argument implemented in another crate"))?;
+                                    return match tcx.hir().find_by_def_id(def_id) {
+                                        Some(Node::ImplItem(ImplItem { kind: ImplItemKind::Fn(_, body_id), .. })) => {
+                                            Ok(*tcx.hir().body(*body_id).value)
                                         }
-                                        _ => panic!(),
-                                    }
+                                        _ => Err(unsupported("argument whose implementation is not a function")),
+                                    };
                                 }
                             }
-                            panic!()
+                            Err(unsupported("argument that do not implement the called method"))
                         } else {
                             // TODO we should return it save for later and recursivly check
-                            todo!()
+                            Err(unsupported("argument implemented in another crate"))
                         }
                     }
-                    _ => panic!(),
+                    _ => Err(unsupported("argument that is not a local variable")),
                 }
             }
-            _ => panic!(),
+            _ => Err(unsupported("argument that is not a resolved path")),
         },
-        ExprKind::Closure(_) => arg,
-        _ => panic!(),
+        ExprKind::Closure(_) => Ok(arg),
+        _ => Err(unsupported("argument that is neither a closure nor a variable")),
     }
 }
 
-fn get_call_in_block<'tcx>(block: &Block<'tcx>, tcx: &mut TyCtxt<'tcx>) -> Vec<Expr<'tcx>> {
-    let mut traverser = FunctionCallPartialTree {
-        tcx: *tcx,
-        visited_functions: HashMap::new(),
-        visited_assoc_functions: HashMap::new(),
-        /// For each allow_panic that we encounter we save the call_stack
-        allow_panics: Vec::new(),
-        save_stack: false,
-        first_level_calls: Vec::new(),
-    };
+fn get_call_in_block<'tcx>(block: &Block<'tcx>, tcx: &TyCtxt<'tcx>) -> Vec<Expr<'tcx>> {
+    let mut traverser = FunctionCallPartialTree::new(*tcx, false);
     traverser.traverse_block(block, &mut Vec::new());
     traverser.first_level_calls
 }
@@ -385,61 +430,34 @@ fn get_arg_number(expr: Expr<'_>, fn_body: &rustc_hir::Body<'_>) -> Option<usize
     None
 }
 
-fn get_function_hir<'hir>(expr: Expr<'hir>) -> rustc_hir::HirId {
-    match expr.kind {
-        ExprKind::Call(ref function, ref _args) => function.hir_id,
-        _ => panic!(),
-    }
-}
-fn get_function_symbol<'hir>(expr: Expr<'hir>) -> rustc_span::Symbol {
-    match expr.kind {
-        ExprKind::Call(ref function, ref _args) => match function.kind {
-            ExprKind::Path(ref path) => match path {
-                rustc_hir::QPath::Resolved(_, path) => path.segments.last().unwrap().ident.name,
-                _ => panic!(),
-            },
-            _ => panic!(),
-        },
-        _ => panic!(),
-    }
-}
-fn get_res<'hir>(expr: Expr<'hir>) -> Option<rustc_hir::def::Res> {
+fn get_res(expr: Expr<'_>) -> Option<rustc_hir::def::Res> {
     match expr.kind {
         ExprKind::Call(function, _) => {
             match function.kind {
-                ExprKind::Path(ref path) => match path {
-                    rustc_hir::QPath::Resolved(_, path) => Some(path.res),
-                    // If it refer to a closure it will be solved
-                    _ => None,
-                },
-                _ => panic!(),
+                ExprKind::Path(rustc_hir::QPath::Resolved(_, path)) => Some(path.res),
+                // If it refer to a closure it will be solved
+                _ => None,
             }
         }
         ExprKind::MethodCall(_, function, _,_) => {
             match function.kind {
-                ExprKind::Path(ref path) => match path {
-                    rustc_hir::QPath::Resolved(_, path) => Some(path.res),
-                    // If it refer to a closure it will be solved
-                    _ => None,
-                },
-                _ => panic!(),
+                ExprKind::Path(rustc_hir::QPath::Resolved(_, path)) => Some(path.res),
+                // If it refer to a closure it will be solved
+                _ => None,
             }
         }
-        _ => {
-            dbg!(expr);
-            panic!();
-        }
+        _ => None,
     }
 }
 
-fn get_hir<'hir>(expr: Expr<'hir>) -> Option<HirId> {
+fn get_hir(expr: Expr<'_>) -> Option<HirId> {
     match get_res(expr)? {
         rustc_hir::def::Res::Local(id) => Some(id),
         // If it refer to a closure it will be local
         _ => None,
     }
 }
-fn from_callers_to_called_def_id<'tcx>(tcx: &mut TyCtxt<'tcx>, expr: Expr<'tcx>) -> Option<DefId> {
+fn from_callers_to_called_def_id<'tcx>(tcx: &TyCtxt<'tcx>, expr: Expr<'tcx>) -> Option<DefId> {
     match expr.kind {
         ExprKind::Call(function, _) => match function.kind {
             ExprKind::Path(path) => match path {
@@ -458,27 +476,26 @@ fn from_callers_to_called_def_id<'tcx>(tcx: &mut TyCtxt<'tcx>, expr: Expr<'tcx>)
                         Some(def_id) => Some(def_id),
                         None => {
                             match path.res {
-                                Res::Local(id) => {
+                                Res::Local(_) => {
                                     let result = tcx.typeck(function.hir_id.owner.def_id);
-                                    let ty = result.expr_ty(function);
                                     let def_id = result
                                         // TODO add comment why we return None here
                                         .type_dependent_def_id(expr.hir_id)?;
                                     Some(def_id)
                                 },
-                                _ => panic!(),
+                                _ => None,
                             }
                         }
                     }
                 }
                 QPath::TypeRelative(_, segment) => segment.res.opt_def_id(),
-                _ => panic!(),
+                _ => None,
             },
             // TODO this should be unreachable
             _ => None,
         },
-        _ => panic!(),
+        _ => None,
     }
 }
 
-//fn resolve_path<'tcx>(&mut tcx: TyCtxt<'tcx>, res: Res
+//fn resolve_path<'tcx>(&tcx: TyCtxt<'tcx>, res: Res
@@ -1,60 +1,96 @@
 //! When we have the def_id of an actual function we can check it, this is kind of the final phase
 //! of the checker, of course if the function is not very simple it will call itself recursively
 //! via the traverser.
-use rustc_hir::HirId;
-use rustc_hir::{def_id::DefId, Node};
-use rustc_middle::hir::map::Map;
+use rustc_hir::def_id::DefId;
 use rustc_middle::ty::TyCtxt;
 
-use super::ForeignCallsToCheck;
-use crate::utils::log_panic_in_deny_block;
+use super::call_frames;
+use crate::report::CallFrame;
 
-pub fn check_fn_panics<'tcx>(
+/// Check a call to an extern function or method, receiver is the type of the receiver of a trait
+/// method
+pub fn check_fn_panics(
     def_id: DefId,
-    fn_ident: String,
-    tcx: &mut TyCtxt<'tcx>,
+    tcx: &TyCtxt<'_>,
     acc: &mut super::ForeignCallsToCheck,
-    call_stack: &[String],
-    to_log: &mut Vec<Vec<String>>,
+    call_stack: &[CallFrame],
+    receiver: Option<DefId>,
+    to_log: &mut Vec<Vec<CallFrame>>,
 ) {
-    let hir_krate = tcx.hir();
-    if !def_id.is_local() {
-        let krate_name = tcx.crate_name(def_id.krate);
-        if is_panic(krate_name.as_str(), fn_ident.as_str()) {
-            to_log.push(call_stack.to_vec());
-        } else {
-            acc.save_for_later_check(def_id, tcx, call_stack, None);
-        }
+    // The local functions are traversed, only the extern ones are checked here
+    if def_id.is_local() {
+        return;
+    }
+    if is_panic(&call_frames::def_path(*tcx, def_id)) {
+        // The last frame is the call to the panicking function
+        to_log.push(call_stack.to_vec());
     } else {
-        panic!()
+        acc.save_for_later_check(def_id, tcx, call_stack, receiver);
     }
 }
 
-pub fn is_panic(krate_name: &str, fn_name: &str) -> bool {
-    let look_for = vec![("std", vec!["begin_panic"]), ("core", vec!["panic"])];
-    for krate in look_for {
-        let krate_name = krate.0;
-        let function_names = krate.1;
-        if krate_name == krate_name && function_names.contains(&fn_name) {
-            return true;
-        }
+/// The functions of core and std that panic. The calls into core and std are not analyzed, so
+/// these are the last frame of the violations. The paths are without the crate name cause the
+/// items of core are also visible from std, eg `std::option::Option::unwrap`, and rustc print the
+/// re-exports of std with their std path, eg `std::rt::panic_fmt` for the `panic!` of the 2021
+/// edition.
+/// The lang items called by the overflow, division by zero and bounds checks (`panic` with an
+/// `attempt to add with overflow` message, `panic_bounds_check`..) are listed too, but the checks
+/// are added by rustc after the hir that unpanic traverses, so they are found only when called.
+const PANICS: [&str; 24] = [
+    "panicking::panic",
+    "panicking::panic_fmt",
+    "panicking::panic_display",
+    "panicking::panic_str",
+    "panicking::panic_explicit",
+    "panicking::panic_nounwind",
+    "panicking::panic_bounds_check",
+    "panicking::panic_misaligned_pointer_dereference",
+    "panicking::panic_cannot_unwind",
+    "panicking::unreachable_display",
+    "panicking::assert_failed",
+    "panicking::begin_panic",
+    "rt::begin_panic",
+    "rt::panic_fmt",
+    "rt::panic_display",
+    "panic::panic_any",
+    "option::expect_failed",
+    "result::unwrap_failed",
+    "option::Option::unwrap",
+    "option::Option::expect",
+    "result::Result::unwrap",
+    "result::Result::expect",
+    "result::Result::unwrap_err",
+    "result::Result::expect_err",
+];
+
+/// The lang items of the overflow and division by zero checks of the newer rustc, one for each
+/// operation, eg `core::panicking::panic_const::panic_const_add_overflow`
+const PANIC_CONST: &str = "panicking::panic_const::";
+
+/// path is the path of the called function, see call_frames::def_path
+pub fn is_panic(path: &str) -> bool {
+    match path
+        .strip_prefix("core::")
+        .or_else(|| path.strip_prefix("std::"))
+    {
+        Some(path) => PANICS.contains(&path) || path.starts_with(PANIC_CONST),
+        None => false,
     }
-    false
 }
 
-/// If is local check it now
-/// If is not save for later
-pub fn check_assoc_fn<'tcx>(
-    def_id: DefId,
-    tcx: &mut TyCtxt<'tcx>,
-    acc: &mut super::ForeignCallsToCheck,
-    receiver: Option<DefId>,
-    call_stack: &[String],
-) {
-    let hir_krate = tcx.hir();
-    if let Some(local_id) = def_id.as_local() {
-        panic!()
-    } else {
-        acc.save_for_later_check(def_id, tcx, call_stack, receiver);
-    }
+#[test]
+fn test_is_panic() {
+    assert!(is_panic("core::panicking::panic"));
+    assert!(is_panic("core::panicking::panic_fmt"));
+    assert!(is_panic("std::rt::begin_panic"));
+    assert!(is_panic("core::option::Option::unwrap"));
+    assert!(is_panic("std::result::Result::expect"));
+    assert!(is_panic("std::rt::panic_fmt"));
+    assert!(is_panic("core::panicking::panic_bounds_check"));
+    assert!(is_panic(
+        "core::panicking::panic_const::panic_const_add_overflow"
+    ));
+    assert!(!is_panic("core::option::Option::unwrap_or"));
+    assert!(!is_panic("test1_lib::panicking::panic"));
 }
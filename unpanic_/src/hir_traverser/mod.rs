@@ -1,19 +1,26 @@
 //! use rustc_interface::run_compiler to check the hir of the target crate and the dependency if
 //! there is any forbidden panic. If there is it will report it.
-use rustc_hir::def_id::DefId;
-use rustc_hir::{Block, BodyId, ExprKind, HirId};
+use rustc_hir::def_id::{CrateNum, DefId, DefPathHash, LOCAL_CRATE};
+use rustc_hir::{Block, BodyId, HirId};
 use rustc_interface::Config;
 use rustc_middle::ty::AssocItem;
 use rustc_middle::ty::Ty;
 use rustc_middle::ty::TyCtxt;
+use rustc_span::Span;
 
 use std::collections::HashSet;
 use std::{collections::HashMap, path::PathBuf};
 
-use crate::utils::config_from_args;
-use crate::utils::{log_allow_panic, log_panic_in_deny_block};
-use std::collections::VecDeque;
+use crate::config::UnpanicConfig;
+use crate::dep_handler::{unit_crate_name, CrateEntry};
+use crate::errors::Error;
+use crate::report::{
+    AllowPanic, CallFrame, Metadata, Report, UnresolvedCall, Violation, REPORT_VERSION,
+};
+use crate::utils::{config_from_args, CrateEnv};
+use std::time::Instant;
 
+mod call_frames;
 mod function_collectors;
 mod function_handlers;
 //mod path_handlers;
@@ -25,22 +32,34 @@ use function_collectors::{
 use traversers::FunctionCallPartialTree;
 
 pub struct HirTraverser {
-    pub errors: Vec<String>,
     pub function_to_check: ForeignCallsToCheck,
     //pub indirect_function_to_check: ForeignCallsToCheck,
-    pub target_args: Vec<String>,
-    pub dep_map: HashMap<
-        /* krate name */ String,
-        /*args:*/ (/* buildrs*/ Option<Vec<String>>, Vec<String>),
-    >,
+    pub targets: Vec<CrateEntry>,
+    /// Keyed by unit name, see CrateEntry::unit_name
+    pub dep_map: HashMap</* unit name */ String, CrateEntry>,
     pub sysroot: PathBuf,
+    /// Where unpanic.toml is, the paths in the report are relative to it
+    pub workspace_root: PathBuf,
     pub visited_functions: Vec<HirId>,
-    pub deny_panic_procedural_parameters: HashMap<DefId, HashMap<usize, DefId>>,
+    /// The def path hashes and not the def ids, they are collected in a session and used in the
+    /// sessions of the other crates
+    pub deny_panic_procedural_parameters: HashMap<DefPathHash, HashMap<usize, DefPathHash>>,
+    /// The unit names of the analyzed crates
     pub vistited_crates: HashSet<String>,
-    pub to_log: Vec<Vec<String>>,
+    pub to_log: Vec<Vec<CallFrame>>,
+    /// For each allow_panic that we encounter we save the call_stack
+    pub allow_panics: Vec<Vec<CallFrame>>,
+    /// The call stacks that lead to a call into a crate that has not been analyzed
+    pub unresolved_calls: Vec<Vec<CallFrame>>,
+    /// The functions that contain a 'deny_panic block, the first frame of the call stacks in
+    /// to_log
+    pub deny_blocks: Vec<CallFrame>,
+    pub config: UnpanicConfig,
+    /// The targets and the recorded dependencies, to log the progress of the analysis
+    pub crates_to_analyze: usize,
 }
 
-/// Given the target crate do:
+/// Given the target crates do:
 /// 1. traverse the hir to get all the functions that contains a block labelled 'deny_panic inside
 ///    the body, for each of those function
 /// 2. apply check function to each of those function
@@ -54,92 +73,85 @@ pub struct HirTraverser {
 ///    3. save all the non local function call inside the function body in a map
 impl HirTraverser {
     pub fn new(
-        target_args: Vec<String>,
-        dep_map: HashMap<String, (Option<Vec<String>>, Vec<String>)>,
+        targets: Vec<CrateEntry>,
+        dep_map: HashMap<String, CrateEntry>,
         sysroot: PathBuf,
+        workspace_root: PathBuf,
+        config: UnpanicConfig,
     ) -> Self {
         Self {
-            errors: Vec::new(),
             function_to_check: ForeignCallsToCheck::new(),
-            target_args,
+            targets,
             dep_map,
             sysroot,
+            workspace_root,
             visited_functions: Vec::new(),
             deny_panic_procedural_parameters: HashMap::new(),
             vistited_crates: HashSet::new(),
             to_log: Vec::new(),
+            allow_panics: Vec::new(),
+            unresolved_calls: Vec::new(),
+            deny_blocks: Vec::new(),
+            config,
+            crates_to_analyze: 0,
         }
     }
 
-    pub fn start(&mut self) {
-        self.dep_map.remove("std");
-        self.dep_map.remove("alloc");
-        self.dep_map.remove("core");
-        self.dep_map.remove("getrandom");
-        self.dep_map.remove("secp256k1");
-        self.dep_map.remove("bitcoin_hashes");
-        self.dep_map.remove("bitcoin");
-        self.dep_map.remove("async-recursion");
-        self.dep_map.remove("async_trait");
-        //self.dep_map.remove("proc_macro");
-        //self.dep_map.remove("proc_macro2");
-        //self.dep_map.remove("tracing");
-        //self.dep_map.remove("tracing_core");
-        self.dep_map.remove("tracing_attributes");
-        //self.dep_map.remove("once_cell");
-        //self.dep_map.remove("cfg_if");
-        //self.dep_map.remove("aes_gcm");
-        let target_config = config_from_args(&self.target_args, &self.sysroot);
-        self.check_crate(target_config, None);
-        self.dep_map
-            .insert("SELF".to_string(), (None, self.target_args.clone()));
-        while !self.function_to_check.keys().is_empty() {
-            dbg!("PRIMO CICLO");
-            for crate_ in self.function_to_check.keys() {
-                let to_check = self
-                    .function_to_check
-                    .remove(&crate_)
-                    .expect("ERROR: No crate in deps map");
-                match crate_.as_str() {
-                    "std" | "alloc" | "core" => (),
-                    _ => {
-                        let (_, dep_args) = self
-                            .dep_map
-                            .get_mut(&crate_)
-                            .expect("ERROR: No crate in deps map");
-                        let target_config = config_from_args(dep_args, &self.sysroot);
-                        self.vistited_crates.insert(crate_.to_string());
-                        self.check_crate(target_config, Some(to_check));
-                    }
-                };
-            }
-        }
-        // Make sure to check all the crates
-        let mut not_checked_crates = vec![];
-        for crate_name in self.dep_map.keys() {
-            if !self.vistited_crates.contains(crate_name) {
-                not_checked_crates.push(crate_name.clone());
+    pub fn start(&mut self) -> Result<Report, Error> {
+        #[allow(unused_labels)]
+        'deny_panic: {
+            // std, alloc, core and the crates skipped in unpanic.toml
+            let config = &self.config;
+            self.dep_map.retain(|_, entry| {
+                !matches!(entry.crate_name.as_str(), "std" | "alloc" | "core")
+                    && !config.is_skipped(&entry.crate_name)
+            });
+            let started = Instant::now();
+            let dep_map = &self.dep_map;
+            self.crates_to_analyze = dep_map.len()
+                + self
+                    .targets
+                    .iter()
+                    .filter(|entry| !dep_map.contains_key(&entry.unit_name()))
+                    .count();
+            // Check all the targets before the dependencies so that the calls into a dependency
+            // shared by more targets are checked together
+            for entry in self.targets.clone() {
+                let target_config = config_from_args(&entry.args, &self.sysroot)?;
+                let _crate_env = CrateEnv::set(&entry)?;
+                self.check_crate(&entry.unit_name(), target_config, None)?;
+                // A target can be a dependency of another target
+                self.dep_map.entry(entry.unit_name()).or_insert(entry);
             }
-        }
-        for crate_name in not_checked_crates {
-            let (_, dep_args) = self
-                .dep_map
-                .get_mut(&crate_name)
-                .expect("ERROR: No crate in deps map");
-            match crate_name.as_str() {
-                "std" | "alloc" | "core" => (),
-                _ => {
-                    let (_, dep_args) = self
-                        .dep_map
-                        .get_mut(&crate_name)
-                        .expect("ERROR: No crate in deps map");
-                    let target_config = config_from_args(dep_args, &self.sysroot);
-                    self.vistited_crates.insert(crate_name.to_string());
-                    self.check_crate(target_config, None);
+            self.check_foreign_calls()?;
+            // Make sure to check all the crates
+            let mut not_checked_crates = vec![];
+            for unit in self.dep_map.keys() {
+                if !self.vistited_crates.contains(unit) {
+                    not_checked_crates.push(unit.clone());
                 }
-            };
+            }
+            for unit in not_checked_crates {
+                let entry = self
+                    .dep_map
+                    .get(&unit)
+                    .ok_or_else(|| Error::CrateNotRecorded(unit.clone()))?;
+                let target_config = config_from_args(&entry.args, &self.sysroot)?;
+                let _crate_env = CrateEnv::set(entry)?;
+                self.check_crate(&unit, target_config, None)?;
+            }
+            self.second_pass()?;
+            debug!(
+                "analyzed {} crates in {:.2?}: {} panics reachable from 'deny_panic blocks, {} \
+                 'allow_panic blocks reached, {} calls into crates that have not been analyzed",
+                self.vistited_crates.len(),
+                started.elapsed(),
+                self.to_log.len(),
+                self.allow_panics.len(),
+                self.unresolved_calls.len()
+            );
+            Ok(self.report())
         }
-        self.second_pass();
     }
 
     /// For each function function to check call get_panic_in_block for the function block.
@@ -148,136 +160,83 @@ impl HirTraverser {
     #[allow(clippy::type_complexity)]
     fn check_crate(
         &mut self,
+        unit: &str,
         target_config: Config,
-        function_to_check: Option<Vec<(DefId, Vec<String>, Option<DefId>)>>,
-    ) {
-        dbg!("SECONDO CICLO");
-        rustc_interface::run_compiler(target_config, |compiler| {
-            compiler.enter(|queries| {
-                queries
-                    .global_ctxt()
-                    .expect("ERROR: Can not get global context")
-                    .enter(|mut tcx| {
-                        let ids = match function_to_check {
-                            Some(ids) => {
-                                let called_functions_to_check =
-                                    get_function_for_dependency(&mut tcx, ids);
-                                let deny_panic_functions = get_functions(&mut tcx);
-                                let mut ret = called_functions_to_check;
-                                for (id, blocks) in deny_panic_functions {
-                                    if !ret.iter().any(|el| el.0 == id) {
-                                        ret.push((id, blocks.clone()));
+        function_to_check: Option<Vec<(DefId, Vec<CallFrame>, Option<DefId>)>>,
+    ) -> Result<(), Error> {
+        #[allow(unused_labels)]
+        'deny_panic: {
+            let crate_name = unit_crate_name(unit);
+            if self.vistited_crates.insert(unit.to_string()) {
+                info!(
+                    "analyzing {} ({}/{})",
+                    crate_name,
+                    self.vistited_crates.len(),
+                    self.crates_to_analyze
+                );
+            } else {
+                debug!(
+                    "analyzing {} again for the calls from other crates",
+                    crate_name
+                );
+            }
+            let started = Instant::now();
+            let panics = self.to_log.len();
+            let checked = rustc_interface::run_compiler(target_config, |compiler| {
+                compiler.enter(|queries| -> Result<usize, Error> {
+                    queries
+                        .global_ctxt()
+                        .map_err(|_| compiler_session_error(crate_name))?
+                        .enter(|tcx| -> Result<usize, Error> {
+                            let ids = match function_to_check {
+                                Some(ids) => {
+                                    let called_functions_to_check = get_function_for_dependency(
+                                        &tcx,
+                                        ids,
+                                        &mut self.allow_panics,
+                                    )?;
+                                    let deny_panic_functions = get_functions(&tcx);
+                                    self.save_deny_blocks(&deny_panic_functions);
+                                    let mut ret = called_functions_to_check;
+                                    for (id, blocks) in deny_panic_functions {
+                                        if !ret.iter().any(|el| el.0 == id) {
+                                            ret.push((id, blocks.clone()));
+                                        }
                                     }
+                                    let procedural_parameters =
+                                        get_procedural_parameters(&tcx, &ret);
+                                    self.deny_panic_procedural_parameters
+                                        .extend(procedural_parameters);
+                                    ret
+                                }
+                                None => {
+                                    let ret = get_functions(&tcx);
+                                    self.save_deny_blocks(&ret);
+                                    let procedural_parameters =
+                                        get_procedural_parameters(&tcx, &ret);
+                                    self.deny_panic_procedural_parameters
+                                        .extend(procedural_parameters);
+                                    ret
                                 }
-                                let procedural_parameters =
-                                    get_procedural_parameters(&mut tcx, &ret);
-                                self.deny_panic_procedural_parameters
-                                    .extend(procedural_parameters);
-                                ret
-                            }
-                            None => {
-                                let ret = get_functions(&mut tcx);
-                                let procedural_parameters =
-                                    get_procedural_parameters(&mut tcx, &ret);
-                                self.deny_panic_procedural_parameters
-                                    .extend(procedural_parameters);
-                                ret
-                            }
-                        };
-                        for elem in &ids {
-                            self.visited_functions = vec![];
-                            let mut call_stack = elem.1 .1.clone();
-                            let mut traverser = FunctionCallPartialTree {
-                                tcx,
-                                visited_functions: HashMap::new(),
-                                visited_assoc_functions: HashMap::new(),
-                                /// For each allow_panic that we encounter we save the call_stack
-                                allow_panics: Vec::new(),
-                                save_stack: true,
-                                first_level_calls: Vec::new(),
                             };
-                            for block in &elem.1 .0 {
-                                traverser.traverse_block(block, &mut call_stack);
-                            }
-                            dbg!("BLOCCHI ATTAVERSATI");
-                            for (_, (def_id, fn_ident, call_stack)) in traverser
-                                .visited_functions
-                                .iter()
-                                .filter(|x| x.0.is_extern())
-                            {
-                                function_handlers::check_fn_panics(
-                                    *def_id,
-                                    fn_ident.clone(),
-                                    &mut tcx,
-                                    &mut self.function_to_check,
-                                    call_stack,
-                                    &mut self.to_log,
-                                );
-                            }
-                            dbg!("PANIC ATTAVERSATI");
-                            for (_, (def_id, receiver, call_stack)) in traverser
-                                .visited_assoc_functions
-                                .iter()
-                                .filter(|x| x.0.is_extern())
-                            {
-                                self.function_to_check
-                                    .save_for_later_check(*def_id, &mut tcx, call_stack, *receiver);
-                            }
-                            for allow_panic in traverser.allow_panics {
-                                log_allow_panic(&allow_panic);
-                            }
-                        }
-                    })
-            })
-        });
-    }
-
-    fn second_pass(&mut self) {
-        for (k, (_, dep_args)) in self.dep_map.clone().iter() {
-            dbg!(k);
-            let target_config = config_from_args(dep_args, &self.sysroot);
-            rustc_interface::run_compiler(target_config, |compiler| {
-                compiler.enter(|queries| {
-                    queries
-                        .global_ctxt()
-                        .expect("ERROR: Can not get global context")
-                        .enter(|mut tcx| {
-                            let all_fn = get_all_fn_in_crate(&mut tcx);
-                            let callers = get_callers(
-                                &mut tcx,
-                                all_fn,
-                                self.deny_panic_procedural_parameters.clone(),
-                            );
-                            let args_to_check = function_collectors::callers_into_args(callers);
-                            for (arg, to_log, def_id) in args_to_check.iter() {
-                                let arg = function_collectors::solve_arg(
-                                    &mut tcx,
-                                    arg.clone(),
-                                    def_id.clone(),
-                                );
+                            for elem in &ids {
                                 self.visited_functions = vec![];
-                                let mut traverser = FunctionCallPartialTree {
-                                    tcx,
-                                    visited_functions: HashMap::new(),
-                                    visited_assoc_functions: HashMap::new(),
-                                    /// For each allow_panic that we encounter we save the call_stack
-                                    allow_panics: Vec::new(),
-                                    save_stack: true,
-                                    first_level_calls: Vec::new(),
-                                };
-                                let mut call_stack = vec![to_log.clone()];
-                                traverser.traverse_expr(&arg, &mut call_stack);
-                                for (_, (def_id, fn_ident, call_stack)) in traverser
+                                let mut call_stack = elem.1 .1.clone();
+                                let mut traverser = FunctionCallPartialTree::new(tcx, true);
+                                for block in &elem.1 .0 {
+                                    traverser.traverse_block(block, &mut call_stack);
+                                }
+                                for (_, (def_id, _, call_stack)) in traverser
                                     .visited_functions
                                     .iter()
                                     .filter(|x| x.0.is_extern())
                                 {
                                     function_handlers::check_fn_panics(
                                         *def_id,
-                                        fn_ident.clone(),
-                                        &mut tcx,
+                                        &tcx,
                                         &mut self.function_to_check,
                                         call_stack,
+                                        None,
                                         &mut self.to_log,
                                     );
                                 }
@@ -286,50 +245,294 @@ impl HirTraverser {
                                     .iter()
                                     .filter(|x| x.0.is_extern())
                                 {
-                                    self.function_to_check
-                                        .save_for_later_check(
-                                            *def_id, &mut tcx, call_stack, *receiver,
-                                        );
+                                    function_handlers::check_fn_panics(
+                                        *def_id,
+                                        &tcx,
+                                        &mut self.function_to_check,
+                                        call_stack,
+                                        *receiver,
+                                        &mut self.to_log,
+                                    );
                                 }
-                                for allow_panic in traverser.allow_panics {
-                                    log_allow_panic(&allow_panic);
+                                self.allow_panics.extend(traverser.allow_panics);
+                                if let Some(error) = traverser.errors.into_iter().next() {
+                                    return Err(error);
                                 }
                             }
-                        });
-                });
-            });
+                            Ok(ids.len())
+                        })
+                })
+            })?;
+            debug!(
+                "{}: checked {} functions in {:.2?}, {} panics found, calls into {} crates to check",
+                crate_name,
+                checked,
+                started.elapsed(),
+                self.to_log.len().saturating_sub(panics),
+                self.function_to_check.keys().len()
+            );
+            Ok(())
         }
-        // And finally check all the non local calls
-        while !self.function_to_check.keys().is_empty() {
-            dbg!("ULTIMO CICLO");
-            for crate_ in self.function_to_check.keys() {
-                let to_check = self
-                    .function_to_check
-                    .remove(&crate_)
-                    .expect("ERROR: No crate in deps map");
-                match crate_.as_str() {
-                    "std" | "alloc" | "core" => (),
-                    _ => {
-                        let (_, dep_args) = self
-                            .dep_map
-                            .get_mut(&crate_)
-                            .expect("ERROR: No crate in deps map");
-                        let target_config = config_from_args(dep_args, &self.sysroot);
-                        self.vistited_crates.insert(crate_.to_string());
-                        self.check_crate(target_config, Some(to_check));
-                    }
-                };
+    }
+
+    fn second_pass(&mut self) -> Result<(), Error> {
+        #[allow(unused_labels)]
+        'deny_panic: {
+            let dep_map = self.dep_map.clone();
+            for (i, entry) in dep_map.values().enumerate() {
+                let k = &entry.crate_name;
+                info!(
+                    "looking for closures passed to 'deny_panic functions in {} ({}/{})",
+                    k,
+                    i + 1,
+                    dep_map.len()
+                );
+                let started = Instant::now();
+                let target_config = config_from_args(&entry.args, &self.sysroot)?;
+                let _crate_env = CrateEnv::set(entry)?;
+                rustc_interface::run_compiler(target_config, |compiler| {
+                    compiler.enter(|queries| -> Result<(), Error> {
+                        queries
+                            .global_ctxt()
+                            .map_err(|_| compiler_session_error(k))?
+                            .enter(|tcx| -> Result<(), Error> {
+                                let all_fn = get_all_fn_in_crate(&tcx);
+                                let callers = get_callers(
+                                    &tcx,
+                                    all_fn,
+                                    self.deny_panic_procedural_parameters.clone(),
+                                );
+                                let args_to_check =
+                                    function_collectors::callers_into_args(&tcx, callers);
+                                for (arg, stack, def_id) in args_to_check.iter() {
+                                    let arg = function_collectors::solve_arg(&tcx, *arg, *def_id)?;
+                                    self.visited_functions = vec![];
+                                    if let Some(deny_block) = stack.first() {
+                                        if !self.deny_blocks.contains(deny_block) {
+                                            self.deny_blocks.push(deny_block.clone());
+                                        }
+                                    }
+                                    let mut traverser = FunctionCallPartialTree::new(tcx, true);
+                                    let mut call_stack = stack.clone();
+                                    traverser.traverse_expr(&arg, &mut call_stack);
+                                    for (_, (def_id, _, call_stack)) in traverser
+                                        .visited_functions
+                                        .iter()
+                                        .filter(|x| x.0.is_extern())
+                                    {
+                                        function_handlers::check_fn_panics(
+                                            *def_id,
+                                            &tcx,
+                                            &mut self.function_to_check,
+                                            call_stack,
+                                            None,
+                                            &mut self.to_log,
+                                        );
+                                    }
+                                    for (_, (def_id, receiver, call_stack)) in traverser
+                                        .visited_assoc_functions
+                                        .iter()
+                                        .filter(|x| x.0.is_extern())
+                                    {
+                                        function_handlers::check_fn_panics(
+                                            *def_id,
+                                            &tcx,
+                                            &mut self.function_to_check,
+                                            call_stack,
+                                            *receiver,
+                                            &mut self.to_log,
+                                        );
+                                    }
+                                    self.allow_panics.extend(traverser.allow_panics);
+                                    if let Some(error) = traverser.errors.into_iter().next() {
+                                        return Err(error);
+                                    }
+                                }
+                                debug!(
+                                    "{}: checked {} arguments of 'deny_panic functions in {:.2?}",
+                                    k,
+                                    args_to_check.len(),
+                                    started.elapsed()
+                                );
+                                Ok(())
+                            })
+                    })
+                })?;
             }
+            // And finally check all the non local calls
+            self.check_foreign_calls()?;
+            Ok(())
         }
+    }
+
+    /// Check the calls into other crates found so far, checking a crate can find calls into more
+    /// crates so it goes on until there are none left
+    fn check_foreign_calls(&mut self) -> Result<(), Error> {
+        #[allow(unused_labels)]
+        'deny_panic: {
+            while !self.function_to_check.keys().is_empty() {
+                for unit in self.function_to_check.keys() {
+                    let to_check = match self.function_to_check.remove(&unit) {
+                        Some(to_check) => to_check,
+                        None => continue,
+                    };
+                    let crate_ = unit_crate_name(&unit);
+                    // Calls into trusted and skipped crates are not checked
+                    let excluded = self.config.is_trusted(crate_) || self.config.is_skipped(crate_);
+                    match crate_ {
+                        "std" | "alloc" | "core" => (),
+                        _ if excluded => (),
+                        // Crates not compiled by the wrapper, unpanic run as RUSTC_WORKSPACE_WRAPPER
+                        // records only the workspace members
+                        _ if !self.dep_map.contains_key(&unit) => {
+                            self.unresolved_calls
+                                .extend(to_check.into_iter().map(|(_, call_stack, _)| call_stack));
+                        }
+                        _ => {
+                            let entry = self
+                                .dep_map
+                                .get(&unit)
+                                .ok_or_else(|| Error::CrateNotRecorded(unit.clone()))?;
+                            let target_config = config_from_args(&entry.args, &self.sysroot)?;
+                            let _crate_env = CrateEnv::set(entry)?;
+                            self.check_crate(&unit, target_config, Some(to_check))?;
+                        }
+                    };
+                }
+            }
+            Ok(())
+        }
+    }
+
+    /// The same stack can be found more than once if a crate is checked more than once
+    fn report(&self) -> Report {
+        let mut deny_blocks: Vec<CallFrame> = self
+            .deny_blocks
+            .iter()
+            .map(|frame| self.with_version(frame))
+            .collect();
+        deny_blocks.sort_by(|a, b| {
+            (&a.crate_name, &a.path, a.location().map(|l| l.line)).cmp(&(
+                &b.crate_name,
+                &b.path,
+                b.location().map(|l| l.line),
+            ))
+        });
+        let mut violations: Vec<Violation> = vec![];
         for stack in &self.to_log {
-            log_panic_in_deny_block(&stack);
+            let stack: Vec<CallFrame> =
+                stack.iter().map(|frame| self.with_version(frame)).collect();
+            if let Some(violation) = Violation::from_stack(&stack) {
+                if !violations.contains(&violation) {
+                    violations.push(violation);
+                }
+            }
+        }
+        violations.sort_by(|a, b| a.order().cmp(&b.order()));
+        let mut allow_panics: Vec<AllowPanic> = vec![];
+        if self.config.output.allow_panics {
+            for stack in &self.allow_panics {
+                let stack: Vec<CallFrame> =
+                    stack.iter().map(|frame| self.with_version(frame)).collect();
+                if let Some(allow_panic) = AllowPanic::from_stack(&stack) {
+                    if !allow_panics.contains(&allow_panic) {
+                        allow_panics.push(allow_panic);
+                    }
+                }
+            }
+        }
+        allow_panics.sort_by(|a, b| a.order().cmp(&b.order()));
+        let mut unresolved_calls: Vec<UnresolvedCall> = vec![];
+        for stack in &self.unresolved_calls {
+            let stack: Vec<CallFrame> =
+                stack.iter().map(|frame| self.with_version(frame)).collect();
+            if let Some(call) = UnresolvedCall::from_stack(&stack) {
+                if !unresolved_calls.contains(&call) {
+                    unresolved_calls.push(call);
+                }
+            }
+        }
+        unresolved_calls.sort_by(|a, b| a.order().cmp(&b.order()));
+        let mut analyzed_crates: Vec<String> = self
+            .vistited_crates
+            .iter()
+            .map(|unit| unit_crate_name(unit).to_string())
+            .collect();
+        analyzed_crates.sort();
+        analyzed_crates.dedup();
+        Report {
+            version: REPORT_VERSION,
+            metadata: Metadata {
+                unpanic_version: env!("CARGO_PKG_VERSION").to_string(),
+                rustc_version: crate::utils::rustc_version().to_string(),
+                workspace_root: self.workspace_root.clone(),
+                targets: self
+                    .targets
+                    .iter()
+                    .map(|entry| entry.crate_name.clone())
+                    .collect(),
+                analyzed_crates,
+                trusted_crates: self.config.trusted.clone(),
+                skipped_crates: self.config.skip.clone(),
+            },
+            deny_blocks,
+            violations,
+            allow_panics,
+            unresolved_calls,
+            baseline: None,
+        }
+    }
+
+    /// The frames are built by rustc that do not know the package version of the crates. The
+    /// frames do not know the unit, the version is set only if all the units of the crate agree.
+    fn with_version(&self, frame: &CallFrame) -> CallFrame {
+        let mut frame = frame.clone();
+        let mut versions = self
+            .dep_map
+            .values()
+            .filter(|entry| entry.crate_name == frame.crate_name)
+            .map(|entry| &entry.package_version);
+        let version = versions.next().cloned().flatten();
+        if versions.all(|other| *other == version) {
+            frame.crate_version = version;
         }
-        eprintln!("FINISH!!!");
+        frame
+    }
+
+    #[allow(clippy::type_complexity)]
+    fn save_deny_blocks(
+        &mut self,
+        deny_panic_functions: &[(BodyId, (Vec<&Block<'_>>, Vec<CallFrame>))],
+    ) {
+        for (_, (_, call_stack)) in deny_panic_functions {
+            if let Some(function) = call_stack.first() {
+                if !self.deny_blocks.contains(function) {
+                    self.deny_blocks.push(function.clone());
+                }
+            }
+        }
+    }
+}
+
+/// rustc failed to build the hir of crate_name, it has already printed the errors
+fn compiler_session_error(crate_name: &str) -> Error {
+    Error::CompilerSession {
+        crate_name: crate_name.to_string(),
+        reason: "see the errors above".to_string(),
+    }
+}
+
+/// The hir at span is not what unpanic expects
+pub fn analysis_error(tcx: TyCtxt<'_>, span: Span, reason: &str) -> Error {
+    Error::Analysis {
+        crate_name: tcx.crate_name(LOCAL_CRATE).to_string(),
+        location: Some(call_frames::location(tcx, span).to_string()),
+        reason: reason.to_string(),
     }
 }
 
 fn get_impl_item<'tcx>(
-    tcx: &mut TyCtxt<'tcx>,
+    tcx: &TyCtxt<'tcx>,
     trait_fn_def_id: DefId,
     receiver: Option<Ty<'tcx>>,
 ) -> Option<AssocItem> {
@@ -353,15 +556,28 @@ fn get_impl_item<'tcx>(
     //panic!("Impossible to find trait implementation")
 }
 
+/// The unit of a crate loaded by rustc, from the file passed with --extern: the crates with the
+/// same name are told apart by their extra-filename, eg `libtest1_lib-53b4ac4b.rmeta`
+fn unit_name(tcx: TyCtxt<'_>, krate: CrateNum) -> String {
+    tcx.used_crate_source(krate)
+        .paths()
+        .next()
+        .and_then(|path| path.file_stem())
+        .map(|stem| {
+            let stem = stem.to_string_lossy();
+            stem.strip_prefix("lib").unwrap_or(&stem).to_string()
+        })
+        .unwrap_or_else(|| tcx.crate_name(krate).to_string())
+}
 
 #[derive(Debug)]
 pub struct ForeignCallsToCheck {
     #[allow(clippy::type_complexity)]
     inner: HashMap<
-        /* crate_name */ String,
+        /* unit name */ String,
         Vec<(
             /* call to check */ DefId,
-            /* call stack that leads to call to check */ Vec<String>,
+            /* call stack that leads to call to check */ Vec<CallFrame>,
             /* optional receiving type */ Option<DefId>,
         )>,
     >,
@@ -385,18 +601,16 @@ impl ForeignCallsToCheck {
     pub fn save_for_later_check(
         &mut self,
         def_id: DefId,
-        tcx: &mut TyCtxt<'_>,
-        call_stack: &[String],
+        tcx: &TyCtxt<'_>,
+        call_stack: &[CallFrame],
         receiver: Option<DefId>,
     ) {
-        let krate_name = tcx.crate_name(def_id.krate);
-        if let Some(functions) = self.inner.get_mut(&krate_name.to_string()) {
+        let unit = unit_name(*tcx, def_id.krate);
+        if let Some(functions) = self.inner.get_mut(&unit) {
             functions.push((def_id, call_stack.to_owned(), receiver));
         } else {
-            self.inner.insert(
-                krate_name.to_string(),
-                vec![(def_id, call_stack.to_owned(), receiver)],
-            );
+            self.inner
+                .insert(unit, vec![(def_id, call_stack.to_owned(), receiver)]);
         }
     }
 
@@ -405,10 +619,7 @@ impl ForeignCallsToCheck {
     }
 
     #[allow(clippy::type_complexity)]
-    pub fn remove(
-        &mut self,
-        key: &String,
-    ) -> Option<Vec<(DefId, Vec<std::string::String>, Option<DefId>)>> {
+    pub fn remove(&mut self, key: &String) -> Option<Vec<(DefId, Vec<CallFrame>, Option<DefId>)>> {
         self.inner.remove(key)
     }
 }
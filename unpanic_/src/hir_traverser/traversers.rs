@@ -1,16 +1,15 @@
 //! This is the core of the hir traverser. It recursivley check an Node (either a Block an Expr or
 //! a Stmt) for call to function that contains panics.
 
-use crate::utils::log_allow_panic;
 use rustc_hir::HirId;
-use rustc_hir::{Block, Expr, ExprKind, Guard, StmtKind,PathSegment,def_id::DefId,QPath,def::{Res,DefKind},Node};
-use rustc_span::Span;
-use rustc_middle::hir::map::Map;
+use rustc_hir::{Block, BodyId, Expr, ExprKind, Guard, StmtKind,def_id::DefId,QPath,def::{Res,DefKind},Node,Item,ItemKind};
 use rustc_middle::ty::TyCtxt;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use rustc_type_ir::sty::TyKind;
 
-use super::ForeignCallsToCheck;
+use super::call_frames;
+use crate::errors::Error;
+use crate::report::{CallFrame, CallKind};
 
 #[derive(Debug,Eq,PartialEq,Hash)]
 pub enum HirId_ {
@@ -71,12 +70,17 @@ impl HirId_ {
 ///
 pub struct FunctionCallPartialTree<'tcx> {
     pub tcx: TyCtxt<'tcx>,
-    pub visited_functions: HashMap<HirId_, (DefId,String,Vec<String>)>,
-    pub visited_assoc_functions: HashMap<HirId_,(DefId,Option<DefId>,Vec<String>)>,
+    pub visited_functions: HashMap<HirId_, (DefId,String,Vec<CallFrame>)>,
+    pub visited_assoc_functions: HashMap<HirId_,(DefId,Option<DefId>,Vec<CallFrame>)>,
     /// For each allow_panic that we encounter we save the call_stack
-    pub allow_panics: Vec<Vec<String>>,
+    pub allow_panics: Vec<Vec<CallFrame>>,
     pub save_stack: bool,
     pub first_level_calls: Vec<Expr<'tcx>>,
+    /// The nodes that can not be analyzed, the traversal goes on and the caller decide what to do
+    pub errors: Vec<Error>,
+    /// The bodies of the functions already traversed, the first call stack that reach a function
+    /// is enough and traversing it again for every path is exponential
+    pub traversed_bodies: HashSet<BodyId>,
 }
 
 impl<'tcx> FunctionCallPartialTree<'tcx> {
@@ -90,15 +94,27 @@ impl<'tcx> FunctionCallPartialTree<'tcx> {
            allow_panics: Vec::new(),
            save_stack,
            first_level_calls: Vec::new(),
+           errors: Vec::new(),
+           traversed_bodies: HashSet::new(),
+        }
+    }
+
+    fn traverse_body(
+        &mut self,
+        body_id: BodyId,
+        call_stack: &mut Vec<CallFrame>,
+    ) {
+        if self.traversed_bodies.insert(body_id) {
+            let expr = self.tcx.hir().body(body_id).value;
+            self.traverse_expr(expr,call_stack);
         }
     }
 
     pub fn traverse_block(
         &mut self,
         block: &Block<'tcx>,
-        call_stack: &mut Vec<String>,
+        call_stack: &mut Vec<CallFrame>,
     ) {
-        let hir_krate = self.tcx.hir();
         for stmt in block.stmts {
             self.traverse_stmt(&stmt.kind,call_stack);
         }
@@ -109,9 +125,8 @@ impl<'tcx> FunctionCallPartialTree<'tcx> {
     pub fn traverse_stmt(
         &mut self,
         stmt: &StmtKind<'tcx>,
-        call_stack: &mut Vec<String>,
+        call_stack: &mut Vec<CallFrame>,
     ) {
-        let hir_krate = self.tcx.hir();
         match stmt {
             StmtKind::Local(local) => {
                 if let Some(expr) = local.init {
@@ -134,7 +149,7 @@ impl<'tcx> FunctionCallPartialTree<'tcx> {
     pub fn traverse_expr(
         &mut self,
         expr_: &Expr<'tcx>,
-        call_stack: &mut Vec<String>,
+        call_stack: &mut Vec<CallFrame>,
     ) {
         let hir_krate = self.tcx.hir();
         match expr_.kind {
@@ -149,37 +164,27 @@ impl<'tcx> FunctionCallPartialTree<'tcx> {
             }
             ExprKind::Call(call, args) => {
                 if ! self.save_stack {
-                    self.first_level_calls.push(expr_.clone());
+                    self.first_level_calls.push(*expr_);
                     for arg in args {
                         self.traverse_expr(arg,call_stack);
                     }
                     return;
                 }
-                // I do not want to visit 2 times the same function even if they are called in
-                // different places. I need just the first occurence of a fucntion. If the first
-                // occurence contains a panic this is an error and there is no need to check the
-                // second occurence. 
-                if ! (self.visited_functions.contains_key(&HirId_::Local(call.hir_id.owner.into())) 
-                      || self.visited_functions.contains_key(&HirId_::Extern(call.hir_id.owner.into())))
-                {
-                    self.traverse_expr(call,call_stack);
-                    // TODO args should be checked outside if clause
-                    for expr in args {
-                        self.traverse_expr(expr,call_stack);
-                    }
+                // The body of a local function is traversed only the first time that it is
+                // called (see traverse_body), the calls to extern functions are all recorded
+                self.traverse_expr(call,call_stack);
+                for expr in args {
+                    self.traverse_expr(expr,call_stack);
                 }
             }
             ExprKind::MethodCall(method, receiver, args, span) => {
                 if ! self.save_stack {
-                    self.first_level_calls.push(expr_.clone());
+                    self.first_level_calls.push(*expr_);
                     for arg in args {
                         self.traverse_expr(arg,call_stack);
                     }
                     return;
                 }
-                if ! (self.visited_assoc_functions.contains_key(&HirId_::Local(method.hir_id.owner.into())) 
-                      || self.visited_assoc_functions.contains_key(&HirId_::Extern(method.hir_id.owner.into()))) 
-                    && self.save_stack
                 {
                     let result = self.tcx.typeck(receiver.hir_id.owner.def_id);
                     let ty = result.expr_ty(receiver);
@@ -191,43 +196,44 @@ impl<'tcx> FunctionCallPartialTree<'tcx> {
                     if ! def_id.is_local() {
                         match ty.kind() {
                             rustc_middle::ty::Adt(adt_def, _) => {
-                                Self::add_to_stack(span,call_stack);
                                 // Check if it is a trait method
-                                if let Some(impl_item) = super::get_impl_item(&mut self.tcx,def_id.clone(),Some(ty)) {
-                                    if let Some(local_id) = impl_item.def_id.as_local() {
-                                        match hir_krate.get_by_def_id(local_id) {
-                                            // TraitItem are handled elsewhere TODO
-                                            Node::TraitItem(_) => (),
-                                            Node::ImplItem(item) => {
-                                                if let rustc_hir::ImplItemKind::Fn(_, body_id) = item.kind {
-                                                    let expr = hir_krate.body(body_id).value;
-                                                    Self::add_to_stack(expr.span,call_stack);
-                                                    self.visited_assoc_functions.insert(
-                                                        HirId_::Local(method.hir_id.owner.into()),
-                                                        (
-                                                            def_id,
-                                                            Some(adt_def.did()),
-                                                            call_stack.to_vec(),
-                                                        )
-                                                    );
-                                                    self.traverse_expr(expr, call_stack);
-                                                }
+                                if let Some(impl_item) = super::get_impl_item(&self.tcx,def_id,Some(ty)) {
+                                    match impl_item.def_id.as_local().and_then(|id| hir_krate.find_by_def_id(id)) {
+                                        // TraitItem are handled elsewhere TODO
+                                        Some(Node::TraitItem(_)) => (),
+                                        Some(Node::ImplItem(item)) => {
+                                            if let rustc_hir::ImplItemKind::Fn(_, body_id) = item.kind {
+                                                call_stack.push(call_frames::trait_impl_call(self.tcx,impl_item.def_id,def_id,span));
+                                                self.visited_assoc_functions.insert(
+                                                    HirId_::Local(method.hir_id),
+                                                    (
+                                                        def_id,
+                                                        Some(adt_def.did()),
+                                                        call_stack.to_vec(),
+                                                    )
+                                                );
+                                                self.traverse_body(body_id, call_stack);
+                                                call_stack.pop();
                                             }
-                                            item => panic!("Unexpected Node {:?}", item),
                                         }
-                                    } else {
-                                        unreachable!()
+                                        _ => self.errors.push(super::analysis_error(
+                                            self.tcx,
+                                            span,
+                                            "the implementation of the method is not an impl item of the crate",
+                                        )),
                                     }
                                 // Otherwise save for later check
                                 } else {
+                                    call_stack.push(call_frames::call(self.tcx,def_id,CallKind::Method,span));
                                     self.visited_assoc_functions.insert(
-                                        HirId_::Extern(method.hir_id.owner.into()),
+                                        HirId_::Extern(method.hir_id),
                                         (
                                             def_id,
                                             Some(adt_def.did()),
                                             call_stack.to_vec(),
                                         )
                                     );
+                                    call_stack.pop();
                                 }
                             },
                             _ => self.traverse_expr(receiver,call_stack),
@@ -288,13 +294,17 @@ impl<'tcx> FunctionCallPartialTree<'tcx> {
             }
             ExprKind::Closure(closure) => {
                 let expr = hir_krate.body(closure.body).value;
+                call_stack.push(call_frames::call(self.tcx,closure.def_id.to_def_id(),CallKind::Closure,expr_.span));
                 self.traverse_expr(expr,call_stack);
+                call_stack.pop();
             }
             ExprKind::Block(block, Some(label)) => {
                 if !label.ident.as_str().contains("allow_panic") {
                     self.traverse_block(block,call_stack);
                 } else {
-                    self.allow_panics.push(call_stack.clone());
+                    let mut stack = call_stack.clone();
+                    stack.push(call_frames::allow_block(self.tcx,block.hir_id.owner.to_def_id(),block.span));
+                    self.allow_panics.push(stack);
                 }
             }
             ExprKind::Block(block, None) => {
@@ -319,24 +329,30 @@ impl<'tcx> FunctionCallPartialTree<'tcx> {
                 QPath::Resolved(_,path) => {
                     if let Some(last) = path.segments.last() {
                         let fn_ident = last.ident.as_str().to_string();
-                        Self::add_to_stack(path.span,call_stack);
                         match last.res {
                             Res::Def(DefKind::Fn, def_id) => {
+                                call_stack.push(call_frames::call(self.tcx,def_id,CallKind::Fn,path.span));
                                 if let Some(local_id) = def_id.as_local() {
-                                    let item = hir_krate.expect_item(local_id);
-                                    if let rustc_hir::ItemKind::Fn(_, _, body_id) = item.kind {
-                                        let hir_ = HirId_::Local(last.hir_id.owner.into());
-                                        self.visited_functions.insert(hir_, (def_id,fn_ident,call_stack.clone()));
-                                        let expr = hir_krate.body(body_id).value;
-                                        self.traverse_expr(expr,call_stack);
-                                    } else {
-                                        panic!()
+                                    match hir_krate.find_by_def_id(local_id) {
+                                        Some(Node::Item(Item { kind: ItemKind::Fn(_, _, body_id), .. })) => {
+                                            let hir_ = HirId_::Local(last.hir_id);
+                                            self.visited_functions.insert(hir_, (def_id,fn_ident,call_stack.clone()));
+                                            self.traverse_body(*body_id, call_stack);
+                                        }
+                                        // Functions declared in extern blocks have no body
+                                        Some(Node::ForeignItem(_)) => (),
+                                        _ => self.errors.push(super::analysis_error(
+                                            self.tcx,
+                                            path.span,
+                                            "the called function is not a function item",
+                                        )),
                                     }
                                 } else {
                                     // Extern function are the leafs of the call tree
-                                    let hir_ = HirId_::Extern(last.hir_id.owner.into());
+                                    let hir_ = HirId_::Extern(last.hir_id);
                                     self.visited_functions.insert(hir_, (def_id,fn_ident,call_stack.clone()));
                                 }
+                                call_stack.pop();
                             },
                             // Constructors are conidered safe
                             Res::Def(DefKind::Ctor(_,_), _) => (),
@@ -347,103 +363,101 @@ impl<'tcx> FunctionCallPartialTree<'tcx> {
                             _ => (),
                         }
                     } else {
-                        panic!()
+                        self.errors.push(super::analysis_error(self.tcx, path.span, "empty path"));
                     }
                 },
                 QPath::TypeRelative(_, segment) => {
                     let result = self.tcx.typeck(segment.hir_id.owner.def_id);
-                    let items = result.node_types().items_in_stable_order();
-                    for item in items {
-                        if let TyKind::FnDef(def_id, generic_args) = item.1.kind() {
+                    if let Some(TyKind::FnDef(def_id, generic_args)) = result.node_type_opt(expr_.hir_id).map(|ty| ty.kind()) {
+                        // TODO this seems to be correct the receiver is always the first
+                        // genarg but check it.
+                        let receiver = generic_args.get(0).and_then(
+                            // TODO here we should always have a ty but we dont
+                            |x| x.as_type().and_then(
+                                |ty| match ty.kind() {
+                                    rustc_middle::ty::Adt(adt_def, _) => Some(adt_def.did()),
+                                    // TODO we should never reach this point but we do
+                                    _ => None,
+                                })
+                        );
+                        // If is a trait fn and is implemented solve it and visit the
+                        // implementation
+                        if let Some(impl_item) = super::get_impl_item(
+                            &self.tcx,
+                            *def_id,
                             // TODO this seems to be correct the receiver is always the first
-                            // genarg but check it.
-                            let receiver = generic_args.get(0).map(
-                                // TODO here we should always have a ty but we dont
-                                |x| x.as_type().map(
-                                    |ty| match ty.kind() {
-                                        rustc_middle::ty::Adt(adt_def, _) => Some(adt_def.did()),
-                                        // TODO we should never reach this point but we do
-                                        _ => None,
-                                    })
-                            ).flatten().flatten();
-                            // If is a trait fn and is implemented solve it and visit the
-                            // implementation
-                            if let Some(impl_item) = super::get_impl_item(
-                                &mut self.tcx,
-                                *def_id,
-                                // TODO this seems to be correct the receiver is always the first
-                                // genarg but check it. It should be expect_type
-                                generic_args.get(0).map(|x| x.as_type()).flatten(),
-                            ) {
-                                if let Some(local_id) = impl_item.def_id.as_local() {
-                                    match hir_krate.get_by_def_id(local_id) {
-                                        // TraitItem are handled elsewhere TODO
-                                        Node::TraitItem(_) => (),
-                                        Node::ImplItem(item) => {
-                                            if let rustc_hir::ImplItemKind::Fn(_, body_id) = item.kind {
-                                                let expr = hir_krate.body(body_id).value;
-                                                Self::add_to_stack(expr.span,call_stack);
-                                                self.visited_assoc_functions.insert(
-                                                    HirId_::Local(segment.hir_id.owner.into()),
-                                                    (
-                                                        *def_id,
-                                                        receiver,
-                                                        call_stack.to_vec(),
-                                                    )
-                                                );
-                                                self.traverse_expr(expr, call_stack);
-                                            }
-                                        }
-                                        item => panic!("Unexpected Node {:?}", item),
-                                    }
-                                } else {
-                                    unreachable!()
-                                }
-                            // If is local check if the function contains calls to panic
-                            } else if let Some(local_id) = def_id.as_local() {
-                                if let Some(Node::Item(item)) = hir_krate.find_by_def_id(local_id) {
-                                    if let rustc_hir::ItemKind::Fn(_, _, body_id) = item.kind {
-                                        let expr = hir_krate.body(body_id).value;
-                                        Self::add_to_stack(expr.span,call_stack);
-                                        self.visited_assoc_functions.insert(
-                                            HirId_::Local(segment.hir_id.owner.into()),
-                                            (
-                                                *def_id,
-                                                receiver,
-                                                call_stack.to_vec(),
-                                            )
-                                        );
-                                        self.traverse_expr(expr, call_stack);
-                                    }
-                                };
-                                if let Some(Node::ImplItem(item)) = hir_krate.find_by_def_id(local_id) {
+                            // genarg but check it. It should be expect_type
+                            generic_args.get(0).and_then(|x| x.as_type()),
+                        ) {
+                            match impl_item.def_id.as_local().and_then(|id| hir_krate.find_by_def_id(id)) {
+                                // TraitItem are handled elsewhere TODO
+                                Some(Node::TraitItem(_)) => (),
+                                Some(Node::ImplItem(item)) => {
                                     if let rustc_hir::ImplItemKind::Fn(_, body_id) = item.kind {
-                                        let expr = hir_krate.body(body_id).value;
-                                        Self::add_to_stack(expr.span,call_stack);
+                                        call_stack.push(call_frames::trait_impl_call(self.tcx,impl_item.def_id,*def_id,expr_.span));
                                         self.visited_assoc_functions.insert(
-                                            HirId_::Local(segment.hir_id.owner.into()),
+                                            HirId_::Local(segment.hir_id),
                                             (
                                                 *def_id,
                                                 receiver,
                                                 call_stack.to_vec(),
                                             )
                                         );
-                                        self.traverse_expr(expr, call_stack);
+                                        self.traverse_body(body_id, call_stack);
+                                        call_stack.pop();
                                     }
-                                };
-                            // Otherwise save it for later check
-                            } else {
-                                Self::add_to_stack(expr_.span,call_stack);
-                                self.visited_assoc_functions.insert(
-                                    HirId_::Extern(segment.hir_id.owner.into()),
-                                    (
-                                        *def_id,
-                                        receiver,
-                                        call_stack.to_vec(),
-                                    )
-                                );
-                                // TODO todo
+                                }
+                                _ => self.errors.push(super::analysis_error(
+                                    self.tcx,
+                                    expr_.span,
+                                    "the implementation of the function is not an impl item of the crate",
+                                )),
                             }
+                        // If is local check if the function contains calls to panic
+                        } else if let Some(local_id) = def_id.as_local() {
+                            if let Some(Node::Item(item)) = hir_krate.find_by_def_id(local_id) {
+                                if let rustc_hir::ItemKind::Fn(_, _, body_id) = item.kind {
+                                    call_stack.push(call_frames::call(self.tcx,*def_id,CallKind::Fn,expr_.span));
+                                    self.visited_assoc_functions.insert(
+                                        HirId_::Local(segment.hir_id),
+                                        (
+                                            *def_id,
+                                            receiver,
+                                            call_stack.to_vec(),
+                                        )
+                                    );
+                                    self.traverse_body(body_id, call_stack);
+                                    call_stack.pop();
+                                }
+                            };
+                            if let Some(Node::ImplItem(item)) = hir_krate.find_by_def_id(local_id) {
+                                if let rustc_hir::ImplItemKind::Fn(_, body_id) = item.kind {
+                                    call_stack.push(call_frames::call(self.tcx,*def_id,CallKind::Method,expr_.span));
+                                    self.visited_assoc_functions.insert(
+                                        HirId_::Local(segment.hir_id),
+                                        (
+                                            *def_id,
+                                            receiver,
+                                            call_stack.to_vec(),
+                                        )
+                                    );
+                                    self.traverse_body(body_id, call_stack);
+                                    call_stack.pop();
+                                }
+                            };
+                        // Otherwise save it for later check
+                        } else {
+                            call_stack.push(call_frames::call(self.tcx,*def_id,CallKind::Method,expr_.span));
+                            self.visited_assoc_functions.insert(
+                                HirId_::Extern(segment.hir_id),
+                                (
+                                    *def_id,
+                                    receiver,
+                                    call_stack.to_vec(),
+                                )
+                            );
+                            call_stack.pop();
+                            // TODO todo
                         }
                     }
                 },
@@ -481,14 +495,11 @@ impl<'tcx> FunctionCallPartialTree<'tcx> {
             ExprKind::Yield(expr, _) => {
                 self.traverse_expr(expr,call_stack);
             }
-            ExprKind::Become(_) => todo!(),
-            // TODO why we return here? Why an ExprKind:Err is found?
-            ExprKind::Err(_) => return,
+            ExprKind::Become(expr) => {
+                self.traverse_expr(expr,call_stack);
+            }
+            // TODO why an ExprKind:Err is found?
+            ExprKind::Err(_) => (),
         }
     }
-
-    fn add_to_stack(function: Span, call_stack: &mut Vec<String>) {
-        let formatted = format!("{:?}",function);
-        call_stack.push(formatted);
-    }
 }
@@ -0,0 +1,157 @@
+//! Logs of the analysis, written on stderr so that they do not mix with the reports on stdout.
+//! By default only the findings are printed, `-v` logs the progress of the analysis and `-vv` the
+//! timings and the counts of every crate. UNPANIC_LOG overrides the level for all the modules or
+//! for some of them, eg `UNPANIC_LOG=debug` or `UNPANIC_LOG=info,hir_traverser=trace`.
+use std::fmt;
+use std::str::FromStr;
+use std::sync::OnceLock;
+
+use crate::errors::Error;
+
+static FILTER: OnceLock<Filter> = OnceLock::new();
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl Level {
+    /// `-q` print only the errors, `-v` the progress and `-vv` the details
+    pub fn from_verbosity(quiet: bool, verbose: usize) -> Self {
+        match (quiet, verbose) {
+            (true, _) => Level::Error,
+            (false, 0) => Level::Warn,
+            (false, 1) => Level::Info,
+            (false, _) => Level::Debug,
+        }
+    }
+}
+
+impl FromStr for Level {
+    type Err = Error;
+
+    fn from_str(level: &str) -> Result<Self, Error> {
+        match level {
+            "error" => Ok(Level::Error),
+            "warn" => Ok(Level::Warn),
+            "info" => Ok(Level::Info),
+            "debug" => Ok(Level::Debug),
+            "trace" => Ok(Level::Trace),
+            _ => Err(Error::InvalidArgument(format!(
+                "unknown log level {} in UNPANIC_LOG, expected error, warn, info, debug or trace",
+                level
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let level = match self {
+            Level::Error => "error",
+            Level::Warn => "warning",
+            Level::Info => "info",
+            Level::Debug => "debug",
+            Level::Trace => "trace",
+        };
+        write!(f, "{}", level)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Filter {
+    level: Level,
+    /// Module paths relative to the crate root with their level
+    modules: Vec<(String, Level)>,
+}
+
+impl Filter {
+    /// The filter in UNPANIC_LOG, a comma separated list of `level` and `module=level`, level is
+    /// the level when UNPANIC_LOG do not set one for every module
+    pub fn parse(filter: &str, level: Level) -> Result<Self, Error> {
+        let mut ret = Self {
+            level,
+            modules: vec![],
+        };
+        for directive in filter.split(',').map(str::trim).filter(|d| !d.is_empty()) {
+            match directive.split_once('=') {
+                Some((module, level)) => ret.modules.push((module.to_string(), level.parse()?)),
+                None => ret.level = directive.parse()?,
+            }
+        }
+        Ok(ret)
+    }
+
+    /// The most specific module in the filter that contains module_path decide
+    pub fn enabled(&self, level: Level, module_path: &str) -> bool {
+        // module_path!() starts with the crate name
+        let module_path = module_path
+            .split_once("::")
+            .map_or("", |(_, module_path)| module_path);
+        let max_level = self
+            .modules
+            .iter()
+            .filter(|(module, _)| {
+                module_path == module.as_str() || module_path.starts_with(&format!("{}::", module))
+            })
+            .max_by_key(|(module, _)| module.len())
+            .map_or(self.level, |(_, level)| *level);
+        level <= max_level
+    }
+}
+
+/// Set the filter of the logs, the first call wins
+pub fn init(filter: Filter) {
+    let _ = FILTER.set(filter);
+}
+
+pub fn enabled(level: Level, module_path: &str) -> bool {
+    match FILTER.get() {
+        Some(filter) => filter.enabled(level, module_path),
+        None => level <= Level::Warn,
+    }
+}
+
+macro_rules! log {
+    ($level:expr, $($arg:tt)+) => {
+        if $crate::logger::enabled($level, module_path!()) {
+            eprintln!("{}: {}", $level, format_args!($($arg)+));
+        }
+    };
+}
+
+macro_rules! warn {
+    ($($arg:tt)+) => {
+        log!($crate::logger::Level::Warn, $($arg)+)
+    };
+}
+
+macro_rules! info {
+    ($($arg:tt)+) => {
+        log!($crate::logger::Level::Info, $($arg)+)
+    };
+}
+
+macro_rules! debug {
+    ($($arg:tt)+) => {
+        log!($crate::logger::Level::Debug, $($arg)+)
+    };
+}
+
+#[test]
+fn test_filter() {
+    let filter = Filter::parse("", Level::Info).unwrap();
+    assert!(filter.enabled(Level::Info, "unpanic::hir_traverser"));
+    assert!(!filter.enabled(Level::Debug, "unpanic::hir_traverser"));
+    let filter = Filter::parse("warn, hir_traverser=debug", Level::Info).unwrap();
+    assert!(!filter.enabled(Level::Info, "unpanic"));
+    assert!(filter.enabled(Level::Debug, "unpanic::hir_traverser"));
+    assert!(filter.enabled(Level::Debug, "unpanic::hir_traverser::traversers"));
+    assert!(!filter.enabled(Level::Debug, "unpanic::hir_traverser_"));
+    assert!(Filter::parse("hir_traverser=verbose", Level::Info).is_err());
+    assert_eq!(Level::from_verbosity(false, 2), Level::Debug);
+}
@@ -1,7 +1,5 @@
 #![feature(rustc_private, stmt_expr_attributes)]
-#![feature(string_remove_matches)]
 #![feature(exact_size_is_empty)]
-#![feature(iter_next_chunk)]
 
 use std::process::Command;
 
@@ -20,19 +18,25 @@ extern crate rustc_span;
 extern crate rustc_target;
 extern crate rustc_type_ir;
 
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use rustc_driver::Compilation;
 use rustc_interface::interface::Config;
 
 struct Callbacks;
 
+#[macro_use]
+mod logger;
+mod config;
 mod dep_handler;
 mod errors;
 mod hir_traverser;
+mod report;
 mod rustc_arg_handlers;
 mod utils;
+use config::UnpanicConfig;
 use dep_handler::*;
+use errors::Error;
 use hir_traverser::*;
 use rustc_arg_handlers::*;
 
@@ -51,29 +55,143 @@ impl rustc_driver::Callbacks for Callbacks {
 
 fn main() {
     let args: Vec<String> = std::env::args().collect();
-    if ! is_dependency(&args) {
-        let index = get_target_path_index(&args).ok();
-        let dep_map = parse_deps_args(&args, index);
-        let out = Command::new("rustc")
-            .arg("--print=sysroot")
-            .current_dir(".")
-            .output()
-            .expect("ERROR: Impossible to call rustc in current directory");
-        let sysroot = std::str::from_utf8(&out.stdout)
-            .expect("ERROR: Can not retreive sysroot")
-            .trim();
-        let sysroot = PathBuf::from(sysroot);
-        let mut traverser = HirTraverser::new(args, dep_map, sysroot);
-        traverser.start();
-        return;
+    // `unpanic --analyze <unpanic dir> [--config <unpanic.toml>] [--workspace-root <dir>]
+    // [--format <format>] [--output <file>] [--fail-on <kinds>]` is called by `cargo unpanic`
+    // after the build
+    if args.get(1).map(|s| s.as_str()) == Some("--analyze") {
+        let exit_code = match std::panic::catch_unwind(|| analyze(&args)) {
+            Ok(Ok(exit_code)) => exit_code,
+            Ok(Err(e)) => {
+                eprintln!("error: {}", e);
+                report::EXIT_INCOMPLETE
+            }
+            // A bug in unpanic or in rustc, the panic message has already been printed
+            Err(_) => report::EXIT_INCOMPLETE,
+        };
+        std::process::exit(exit_code);
     }
-    rustc_driver::RunCompiler::new(&args[1..], &mut Callbacks)
+    if let Err(e) = wrap_rustc(args) {
+        eprintln!("error: {}", e);
+        std::process::exit(1);
+    }
+}
+
+/// Compile the crate as rustc would do and record the args in the manifest
+fn wrap_rustc(args: Vec<String>) -> Result<(), Error> {
+    let (wrappers, args) = split_inner_wrappers(&args);
+    if let Some((wrapper, wrapper_args)) = wrappers.split_first() {
+        // Let the inner wrapper (eg sccache) compile, the args are recorded as usual
+        let status = Command::new(wrapper)
+            .args(wrapper_args)
+            .args(&args[1..])
+            .status()
+            .map_err(|e| Error::CannotRunWrapper {
+                wrapper: wrapper.clone(),
+                reason: e.to_string(),
+            })?;
+        if !status.success() {
+            std::process::exit(status.code().unwrap_or(1));
+        }
+    } else if rustc_driver::RunCompiler::new(&args[1..], &mut Callbacks)
         .run()
-        .expect("ERROR: Fail to compile");
+        .is_err()
+    {
+        // rustc already printed the errors
+        std::process::exit(1);
+    }
     if have_arg(&args, "--print=cfg") {
-        return;
+        return Ok(());
+    };
+    // The version of the rustc that write the artifacts, they can be read only by the same version.
+    // `cargo unpanic` set UNPANIC_RUSTC_VERSION to the version of the rustc called by cargo.
+    let rustc_version = if wrappers.is_empty() {
+        utils::rustc_version().to_string()
+    } else {
+        match std::env::var("UNPANIC_RUSTC_VERSION") {
+            Ok(version) => version,
+            Err(_) => utils::get_rustc_version(args[1].as_ref())?,
+        }
     };
     if let Ok(target_path_index) = get_target_path_index(&args) {
-        write_args(args, target_path_index);
+        write_args(args, target_path_index, rustc_version)?;
+    }
+    Ok(())
+}
+
+/// Analyze the crates recorded in the unpanic dir, write the report and return the exit code
+fn analyze(args: &[String]) -> Result<i32, Error> {
+    let unpanic_dir = args
+        .get(2)
+        .ok_or_else(|| Error::MissingArgument("<unpanic dir> after --analyze".to_string()))?;
+    let quiet = args.iter().any(|s| s == "-q");
+    let verbose = args
+        .iter()
+        .map(|s| match s.as_str() {
+            "-v" => 1,
+            "-vv" => 2,
+            _ => 0,
+        })
+        .sum();
+    let log_filter = std::env::var("UNPANIC_LOG").unwrap_or_default();
+    logger::init(logger::Filter::parse(
+        &log_filter,
+        logger::Level::from_verbosity(quiet, verbose),
+    )?);
+    let manifest = parse_deps_args(Path::new(unpanic_dir))?;
+    utils::check_rustc_versions(&manifest)?;
+    let sysroot = utils::get_sysroot()?;
+    let option = |name: &str| {
+        args.iter()
+            .position(|s| s == name)
+            .and_then(|i| args.get(i + 1))
+    };
+    let config_path = option("--config")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from("unpanic.toml"));
+    let format: report::Format =
+        option("--format").map_or(Ok(report::Format::Human), |format| format.parse())?;
+    let fail_on: report::FailOn =
+        option("--fail-on").map_or(Ok(report::FailOn::default()), |fail_on| fail_on.parse())?;
+    let output = option("--output").map(PathBuf::from);
+    let baseline = option("--baseline").map(PathBuf::from);
+    let write_baseline = option("--write-baseline").map(PathBuf::from);
+    if baseline.is_some() && write_baseline.is_some() {
+        return Err(Error::InvalidArgument(
+            "--baseline and --write-baseline can not be used together".to_string(),
+        ));
+    }
+    let workspace_root = option("--workspace-root")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| std::env::current_dir().unwrap_or_default());
+    let mut config = UnpanicConfig::load(&config_path)?;
+    // The 'allow_panic blocks that fail the analysis are always reported
+    config.output.allow_panics |= fail_on.allow_panics;
+    let mut traverser = HirTraverser::new(
+        manifest.targets,
+        manifest.deps,
+        sysroot,
+        workspace_root,
+        config,
+    );
+    let mut report = traverser.start()?;
+    if let Some(path) = &write_baseline {
+        // The current violations are the baseline, so they are all suppressed
+        let new_baseline = report::Baseline::new(&report);
+        new_baseline.write(path)?;
+        info!(
+            "{} violations written in the baseline {}",
+            new_baseline.entries.len(),
+            path.display()
+        );
+        report.apply_baseline(&new_baseline, path);
+    } else if let Some(path) = &baseline {
+        report.apply_baseline(&report::Baseline::load(path)?, path);
     }
+    report
+        .write(format, output.as_deref())
+        .map_err(|e| Error::CannotWriteReport {
+            path: output.clone(),
+            reason: e.to_string(),
+        })?;
+    Ok(report.exit_code(fail_on))
 }
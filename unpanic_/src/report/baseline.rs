@@ -0,0 +1,235 @@
+//! Baseline of the known violations, so that unpanic can be adopted on a codebase that already has
+//! 'deny_panic blocks that can panic and fail only on the new ones.
+//!
+//! An entry is the path of the function with the 'deny_panic block, of the first call out of the
+//! block and of the last call before the panic, without spans, so that the baseline does not
+//! change when the code around the block moves. The panicking function itself is almost always
+//! `core::panicking::panic`, a new call that can panic in a baselined block is a new entry. The
+//! baselined violations are removed from the report, the entries that do not match any violation
+//! of an analyzed crate have been fixed and can be removed from the baseline.
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+use super::{Report, Violation};
+use crate::errors::Error;
+
+/// Bumped every time the baseline file change in a way that can break its readers
+pub const BASELINE_VERSION: u64 = 2;
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Baseline {
+    /// BASELINE_VERSION
+    pub version: u64,
+    /// Sorted, so that the file can be diffed
+    pub entries: Vec<Fingerprint>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Fingerprint {
+    /// The function that contains the 'deny_panic block
+    pub deny_block: String,
+    /// The call out of the 'deny_panic block
+    pub first_call: String,
+    /// The function that calls the panicking function, the panicking function if it is called
+    /// in the block
+    pub last_call: String,
+}
+
+/// What the baseline did to the report
+#[derive(Debug, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct BaselineSummary {
+    pub path: PathBuf,
+    /// Violations removed from the report because they are in the baseline
+    pub suppressed: usize,
+    /// Entries that do not match any violation, they can be removed from the baseline
+    pub fixed: Vec<Fingerprint>,
+}
+
+impl Fingerprint {
+    pub fn new(violation: &Violation) -> Self {
+        let calls = &violation.call_chain;
+        Self {
+            deny_block: violation.deny_block.path.clone(),
+            first_call: calls.first().unwrap_or(&violation.panic).path.clone(),
+            last_call: calls.last().unwrap_or(&violation.panic).path.clone(),
+        }
+    }
+
+    /// The local paths start with the crate name, see call_frames::def_path
+    fn crate_name(&self) -> &str {
+        self.deny_block
+            .split("::")
+            .next()
+            .unwrap_or(&self.deny_block)
+    }
+}
+
+impl Baseline {
+    pub fn new(report: &Report) -> Self {
+        let mut entries: Vec<Fingerprint> =
+            report.violations.iter().map(Fingerprint::new).collect();
+        entries.sort();
+        entries.dedup();
+        Self {
+            version: BASELINE_VERSION,
+            entries,
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let invalid = |reason: String| Error::InvalidBaseline {
+            path: path.to_path_buf(),
+            reason,
+        };
+        let baseline = std::fs::read_to_string(path).map_err(|e| {
+            invalid(format!(
+                "{}, write it with `cargo unpanic --write-baseline`",
+                e
+            ))
+        })?;
+        let baseline: Baseline =
+            serde_json::from_str(&baseline).map_err(|e| invalid(e.to_string()))?;
+        if baseline.version != BASELINE_VERSION {
+            return Err(invalid(format!(
+                "version {}, expected {}: write it again with `cargo unpanic --write-baseline`",
+                baseline.version, BASELINE_VERSION
+            )));
+        }
+        Ok(baseline)
+    }
+
+    pub fn write(&self, path: &Path) -> Result<(), Error> {
+        let cannot_write = |reason: String| Error::CannotWriteBaseline {
+            path: path.to_path_buf(),
+            reason,
+        };
+        let baseline =
+            serde_json::to_string_pretty(self).map_err(|e| cannot_write(e.to_string()))?;
+        std::fs::write(path, baseline + "\n").map_err(|e| cannot_write(e.to_string()))
+    }
+}
+
+impl Report {
+    /// Remove the violations in baseline, path is where baseline has been loaded from
+    pub fn apply_baseline(&mut self, baseline: &Baseline, path: &Path) {
+        let found: Vec<Fingerprint> = self.violations.iter().map(Fingerprint::new).collect();
+        let violations = self.violations.len();
+        self.violations
+            .retain(|violation| !baseline.entries.contains(&Fingerprint::new(violation)));
+        // The entries of the crates that have not been analyzed can not be told fixed
+        let analyzed_crates = &self.metadata.analyzed_crates;
+        let fixed = baseline
+            .entries
+            .iter()
+            .filter(|entry| !found.contains(entry))
+            .filter(|entry| analyzed_crates.iter().any(|c| c == entry.crate_name()))
+            .cloned()
+            .collect();
+        self.baseline = Some(BaselineSummary {
+            path: path.to_path_buf(),
+            suppressed: violations - self.violations.len(),
+            fixed,
+        });
+    }
+}
+
+#[test]
+fn test_baseline() {
+    use super::{test_frame, CallKind, Metadata, REPORT_VERSION};
+    let violation = |deny_block: &str, line: usize, calls: &[&str], panic: &str| {
+        let span = format!("src/lib.rs:{}:1: {}:2", line, line + 2);
+        let mut stack = vec![test_frame(deny_block, CallKind::DenyBlock, &span)];
+        for call in calls {
+            stack.push(test_frame(call, CallKind::Fn, "src/lib.rs:40:5: 40:20"));
+        }
+        stack.push(test_frame(panic, CallKind::Fn, "src/dep.rs:3:5: 3:20"));
+        Violation::from_stack(&stack).unwrap()
+    };
+    let fingerprint = |deny_block: &str, call: &str| Fingerprint {
+        deny_block: deny_block.to_string(),
+        first_call: call.to_string(),
+        last_call: call.to_string(),
+    };
+    let mut report = Report {
+        version: REPORT_VERSION,
+        metadata: Metadata {
+            unpanic_version: "0.1.0".to_string(),
+            rustc_version: "rustc 1.74.0-nightly".to_string(),
+            workspace_root: "/ws".into(),
+            targets: vec!["a".to_string()],
+            analyzed_crates: vec!["a".to_string()],
+            trusted_crates: vec![],
+            skipped_crates: vec![],
+        },
+        deny_blocks: vec![],
+        violations: vec![
+            violation(
+                "a::it_panic",
+                12,
+                &["a::f", "dep::g"],
+                "core::panicking::panic",
+            ),
+            violation("a::it_panic", 12, &[], "core::panicking::panic_fmt"),
+        ],
+        allow_panics: vec![],
+        unresolved_calls: vec![],
+        baseline: None,
+    };
+    let baseline = Baseline::new(&report);
+    assert_eq!(
+        baseline.entries,
+        [
+            Fingerprint {
+                deny_block: "a::it_panic".to_string(),
+                first_call: "a::f".to_string(),
+                last_call: "dep::g".to_string(),
+            },
+            fingerprint("a::it_panic", "core::panicking::panic_fmt"),
+        ]
+    );
+    let path = std::env::temp_dir().join(format!("unpanic-baseline-{}.json", std::process::id()));
+    baseline.write(&path).unwrap();
+    let mut baseline = Baseline::load(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(baseline, Baseline::new(&report));
+    baseline
+        .entries
+        .push(fingerprint("a::fixed", "core::panicking::panic"));
+    baseline
+        .entries
+        .push(fingerprint("b::not_analyzed", "core::panicking::panic"));
+    // The baselined violations are found even if they moved, a new call that panics in a
+    // baselined block is reported
+    report.violations = vec![
+        violation(
+            "a::it_panic",
+            20,
+            &["a::f", "dep::g"],
+            "core::panicking::panic",
+        ),
+        violation("a::it_panic", 20, &["a::h"], "core::panicking::panic"),
+        violation("a::new_panic", 30, &[], "core::panicking::panic"),
+    ];
+    report.apply_baseline(&baseline, &path);
+    let reported: Vec<&str> = report
+        .violations
+        .iter()
+        .map(|v| v.frames().nth(1).unwrap().path.as_str())
+        .collect();
+    assert_eq!(reported, ["a::h", "core::panicking::panic"]);
+    let summary = report.baseline.unwrap();
+    assert_eq!(summary.suppressed, 1);
+    let fixed: Vec<&str> = summary
+        .fixed
+        .iter()
+        .map(|f| f.first_call.as_str())
+        .collect();
+    assert_eq!(
+        fixed,
+        ["core::panicking::panic_fmt", "core::panicking::panic"]
+    );
+    assert!(Baseline::load(&path).is_err());
+}
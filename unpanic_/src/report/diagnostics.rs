@@ -0,0 +1,142 @@
+//! The violations as rustc json diagnostics (`rustc --error-format=json`), one per line, so that
+//! editors can show them inline, eg with `rust-analyzer.check.overrideCommand`.
+//!
+//! The primary spans are the 'deny_panic block and the call that lead to the panic, every other
+//! call in the call chain is a secondary span.
+use serde_json::{json, Value};
+use std::io::Write;
+
+use super::human::render_violation;
+use super::source::Sources;
+use super::{CallFrame, Location, Report, Violation};
+
+pub fn write_diagnostics(report: &Report, out: &mut dyn Write) -> std::io::Result<()> {
+    let mut sources = Sources::new(&report.metadata.workspace_root);
+    for violation in &report.violations {
+        let diagnostic = diagnostic(violation, &mut sources);
+        serde_json::to_writer(&mut *out, &diagnostic)?;
+        writeln!(out)?;
+    }
+    Ok(())
+}
+
+fn diagnostic(violation: &Violation, sources: &mut Sources<'_>) -> Value {
+    let panic = violation.panic.to_string();
+    let calls: Vec<&CallFrame> = violation
+        .call_chain
+        .iter()
+        .chain(Some(&violation.panic))
+        .collect();
+    // The call that lead to the panic is the last one with a location
+    let offending_call = calls.iter().rposition(|frame| frame.location().is_some());
+    let mut spans = vec![];
+    if let Some(location) = violation.deny_block.location() {
+        spans.push(span(
+            location,
+            true,
+            "this 'deny_panic block can panic",
+            sources,
+        ));
+    }
+    for (i, frame) in calls.iter().enumerate() {
+        let location = match frame.location() {
+            Some(location) => location,
+            None => continue,
+        };
+        let label = format!("{} is called here", frame);
+        spans.push(span(location, Some(i) == offending_call, &label, sources));
+    }
+    let message = format!("'deny_panic block can panic: {} can be called", panic);
+    let call_chain: Vec<String> = violation.frames().map(CallFrame::to_string).collect();
+    let note = format!("call chain:\n    {}", call_chain.join("\n    "));
+    let rendered = render_violation(violation, sources);
+    json!({
+        "$message_type": "diagnostic",
+        "message": message,
+        "code": { "code": "deny_panic", "explanation": null },
+        "level": "error",
+        "spans": spans,
+        "children": [{
+            "message": note,
+            "code": null,
+            "level": "note",
+            "spans": [],
+            "children": [],
+            "rendered": null,
+        }],
+        "rendered": rendered,
+    })
+}
+
+fn span(location: &Location, is_primary: bool, label: &str, sources: &mut Sources<'_>) -> Value {
+    let (byte_start, byte_end) = sources.byte_range(location).unwrap_or((0, 0));
+    let text: Vec<Value> = sources
+        .lines(location)
+        .unwrap_or_default()
+        .iter()
+        .enumerate()
+        .map(|(i, text)| {
+            let line = location.line + i;
+            let highlight_start = if line == location.line {
+                location.column
+            } else {
+                1
+            };
+            let highlight_end = if line == location.end_line {
+                location.end_column
+            } else {
+                text.chars().count() + 1
+            };
+            json!({
+                "text": text,
+                "highlight_start": highlight_start,
+                "highlight_end": highlight_end,
+            })
+        })
+        .collect();
+    json!({
+        "file_name": location.file,
+        "byte_start": byte_start,
+        "byte_end": byte_end,
+        "line_start": location.line,
+        "line_end": location.end_line,
+        "column_start": location.column,
+        "column_end": location.end_column,
+        "is_primary": is_primary,
+        "text": text,
+        "label": label,
+        "suggested_replacement": null,
+        "suggestion_applicability": null,
+        "expansion": null,
+    })
+}
+
+#[test]
+fn test_diagnostic() {
+    use super::{test_frame, CallKind};
+    let stack = vec![
+        test_frame("a::it_panic", CallKind::DenyBlock, "src/lib.rs:12:1: 14:2"),
+        test_frame("a::dep::foo", CallKind::Fn, "src/lib.rs:13:5: 13:20"),
+        test_frame(
+            "core::panicking::panic",
+            CallKind::Fn,
+            "src/dep.rs:3:5: 3:20",
+        ),
+    ];
+    let violation = Violation::from_stack(&stack).unwrap();
+    let workspace_root = std::path::PathBuf::from("/not/a/workspace");
+    let diagnostic = diagnostic(&violation, &mut Sources::new(&workspace_root));
+    assert_eq!(diagnostic["level"], "error");
+    let spans = diagnostic["spans"].as_array().unwrap();
+    assert_eq!(spans.len(), 3);
+    assert_eq!(spans[0]["line_start"], 12);
+    assert_eq!(spans[0]["is_primary"], true);
+    assert_eq!(spans[1]["is_primary"], false);
+    assert_eq!(spans[1]["label"], "`a::dep::foo` is called here");
+    assert_eq!(spans[2]["file_name"], "src/dep.rs");
+    assert_eq!(spans[2]["is_primary"], true);
+    assert_eq!(spans[2]["label"], "`core::panicking::panic` is called here");
+    assert!(diagnostic["rendered"].as_str().unwrap().starts_with(
+        "error: `a::it_panic` has a 'deny_panic block that can panic\n  --> src/lib.rs:12:1"
+    ));
+}
@@ -0,0 +1,285 @@
+//! Human readable report, rendered like the rustc diagnostics: every frame of a call chain is
+//! shown as a snippet of the source code with the span underlined.
+use std::fmt::Write as _;
+use std::io::Write;
+
+use super::source::Sources;
+use super::{AllowPanic, CallFrame, Location, Report, Violation};
+
+const HELP: &str =
+    "if the panic can not happen put the call in an 'allow_panic block, otherwise use a \
+     non-panicking alternative (eg `get` instead of indexing, `checked_*` arithmetic or a match \
+     instead of `unwrap`)";
+
+pub fn write_human(report: &Report, out: &mut dyn Write) -> std::io::Result<()> {
+    let mut sources = Sources::new(&report.metadata.workspace_root);
+    for allow_panic in &report.allow_panics {
+        writeln!(out, "{}", render_allow_panic(allow_panic, &mut sources))?;
+    }
+    for violation in &report.violations {
+        writeln!(out, "{}", render_violation(violation, &mut sources))?;
+    }
+    if !report.unresolved_calls.is_empty() {
+        let mut crates: Vec<String> = report
+            .unresolved_calls
+            .iter()
+            .filter_map(|call| call.call_chain.last())
+            .map(|call| format!("`{}`", call.crate_name))
+            .collect();
+        crates.sort();
+        crates.dedup();
+        writeln!(
+            out,
+            "warning: {} call(s) from 'deny_panic blocks into crates that have not been analyzed: \
+             {}",
+            report.unresolved_calls.len(),
+            crates.join(", ")
+        )?;
+    }
+    if let Some(baseline) = &report.baseline {
+        if baseline.suppressed > 0 {
+            writeln!(
+                out,
+                "note: {} violation(s) in the baseline {} are not reported",
+                baseline.suppressed,
+                baseline.path.display()
+            )?;
+        }
+        if !baseline.fixed.is_empty() {
+            writeln!(
+                out,
+                "warning: {} entries of the baseline {} have been fixed, remove them or write the \
+                 baseline again with `--write-baseline`",
+                baseline.fixed.len(),
+                baseline.path.display()
+            )?;
+            for entry in &baseline.fixed {
+                writeln!(
+                    out,
+                    "  = note: `{}` can not panic via `{}` and `{}` anymore",
+                    entry.deny_block, entry.first_call, entry.last_call
+                )?;
+            }
+        }
+    }
+    // The violations are sorted by crate name
+    let mut crates: Vec<&str> = report
+        .violations
+        .iter()
+        .map(|v| v.crate_name.as_str())
+        .collect();
+    crates.dedup();
+    for crate_name in crates {
+        let mut blocks: Vec<&CallFrame> = vec![];
+        for violation in &report.violations {
+            if violation.crate_name == crate_name && !blocks.contains(&&violation.deny_block) {
+                blocks.push(&violation.deny_block);
+            }
+        }
+        writeln!(
+            out,
+            "error: {} 'deny_panic block(s) can panic in `{}`",
+            blocks.len(),
+            crate_name
+        )?;
+    }
+    Ok(())
+}
+
+pub fn render_violation(violation: &Violation, sources: &mut Sources<'_>) -> String {
+    let width = gutter_width(violation.frames());
+    let mut ret = String::new();
+    let _ = writeln!(
+        ret,
+        "error: {} has a 'deny_panic block that can panic",
+        violation.deny_block
+    );
+    let label = format!("this 'deny_panic block can reach {}", violation.panic);
+    snippet(&mut ret, &violation.deny_block, &label, width, sources);
+    for frame in violation.call_chain.iter().chain(Some(&violation.panic)) {
+        called_here(&mut ret, frame, width, sources);
+    }
+    let _ = writeln!(
+        ret,
+        "{:w$} = note: {} panics",
+        "",
+        violation.panic,
+        w = width
+    );
+    let _ = writeln!(ret, "{:w$} = help: {}", "", HELP, w = width);
+    ret
+}
+
+fn render_allow_panic(allow_panic: &AllowPanic, sources: &mut Sources<'_>) -> String {
+    let width = gutter_width(allow_panic.frames());
+    let mut ret = String::from("warning: 'allow_panic block reached from a 'deny_panic block\n");
+    let (allow_block, calls) = match allow_panic.call_chain.split_last() {
+        Some((allow_block, calls)) => (allow_block, calls),
+        None => (&allow_panic.deny_block, &[][..]),
+    };
+    let label = "the panics in this block are allowed";
+    snippet(&mut ret, allow_block, label, width, sources);
+    ret.push_str("note: reached from this 'deny_panic block\n");
+    snippet(&mut ret, &allow_panic.deny_block, "", width, sources);
+    for frame in calls {
+        called_here(&mut ret, frame, width, sources);
+    }
+    ret
+}
+
+fn called_here(ret: &mut String, frame: &CallFrame, width: usize, sources: &mut Sources<'_>) {
+    match frame.location() {
+        Some(_) => {
+            let _ = writeln!(ret, "note: {} is called here", frame);
+            snippet(ret, frame, "", width, sources);
+        }
+        None => {
+            let _ = writeln!(ret, "note: {} is called", frame);
+        }
+    }
+}
+
+/// Width of the line numbers
+fn gutter_width<'a>(frames: impl Iterator<Item = &'a CallFrame>) -> usize {
+    frames
+        .filter_map(CallFrame::location)
+        .map(|location| location.end_line.to_string().len())
+        .max()
+        .unwrap_or(1)
+}
+
+/// The location of frame and the source code of the location, with the span underlined
+fn snippet(
+    ret: &mut String,
+    frame: &CallFrame,
+    label: &str,
+    width: usize,
+    sources: &mut Sources<'_>,
+) {
+    let location = match frame.location() {
+        Some(location) => location,
+        None => return,
+    };
+    let _ = writeln!(
+        ret,
+        "{:w$}--> {}:{}:{}",
+        "",
+        location.file,
+        location.line,
+        location.column,
+        w = width
+    );
+    let lines = match sources.lines(location) {
+        Some(lines) => lines,
+        None => return,
+    };
+    let _ = writeln!(ret, "{:w$} |", "", w = width);
+    match lines.as_slice() {
+        [line] => single_line(ret, location, line, label, width),
+        [first, .., last] => multi_line(ret, location, &lines, first, last, label, width),
+        [] => (),
+    }
+    let _ = writeln!(ret, "{:w$} |", "", w = width);
+}
+
+fn single_line(ret: &mut String, location: &Location, line: &str, label: &str, width: usize) {
+    let _ = writeln!(ret, "{:<w$} | {}", location.line, line, w = width);
+    let start = location.column.saturating_sub(1);
+    let carets = location.end_column.saturating_sub(location.column).max(1);
+    let underline = format!("{}{} {}", " ".repeat(start), "^".repeat(carets), label);
+    let _ = writeln!(ret, "{:w$} | {}", "", underline.trim_end(), w = width);
+}
+
+/// Multi line spans are marked on the left of the source code, like rustc do
+fn multi_line(
+    ret: &mut String,
+    location: &Location,
+    lines: &[String],
+    first: &str,
+    last: &str,
+    label: &str,
+    width: usize,
+) {
+    let starts_the_line = first
+        .chars()
+        .take(location.column.saturating_sub(1))
+        .all(char::is_whitespace);
+    if starts_the_line {
+        let _ = writeln!(ret, "{:<w$} | / {}", location.line, first, w = width);
+    } else {
+        let _ = writeln!(ret, "{:<w$} |   {}", location.line, first, w = width);
+        let underline = "_".repeat(location.column);
+        let _ = writeln!(ret, "{:w$} |  {}^", "", underline, w = width);
+    }
+    let middle = &lines[1..lines.len() - 1];
+    for (i, line) in middle.iter().enumerate() {
+        // Long spans are cut like rustc do
+        if middle.len() > 4 && i == 2 {
+            ret.push_str("...\n");
+        }
+        if middle.len() > 4 && i >= 2 && i < middle.len() - 1 {
+            continue;
+        }
+        let line_number = location.line + 1 + i;
+        let _ = writeln!(ret, "{:<w$} | | {}", line_number, line, w = width);
+    }
+    let _ = writeln!(ret, "{:<w$} | | {}", location.end_line, last, w = width);
+    let underline = format!(
+        "|{}^ {}",
+        "_".repeat(location.end_column.saturating_sub(1)),
+        label
+    );
+    let _ = writeln!(ret, "{:w$} | {}", "", underline.trim_end(), w = width);
+}
+
+#[test]
+fn test_render_violation() {
+    use super::{test_frame, CallKind};
+    let dir = std::env::temp_dir().join(format!("unpanic-human-{}", std::process::id()));
+    std::fs::create_dir_all(dir.join("src")).unwrap();
+    std::fs::write(
+        dir.join("src/lib.rs"),
+        "fn it_panic() {\n    'deny_panic: {\n        foo(1);\n    }\n}\n\nfn foo(a: u8) {\n    \
+         panic!()\n}\n",
+    )
+    .unwrap();
+    let stack = vec![
+        test_frame("a::it_panic", CallKind::DenyBlock, "src/lib.rs:2:5: 4:6"),
+        test_frame("a::foo", CallKind::Fn, "src/lib.rs:3:9: 3:12"),
+        test_frame(
+            "core::panicking::panic",
+            CallKind::Fn,
+            "src/lib.rs:8:5: 8:13",
+        ),
+    ];
+    let violation = Violation::from_stack(&stack).unwrap();
+    let rendered = render_violation(&violation, &mut Sources::new(&dir));
+    std::fs::remove_dir_all(&dir).unwrap();
+    let expected = "\
+error: `a::it_panic` has a 'deny_panic block that can panic
+ --> src/lib.rs:2:5
+  |
+2 | /     'deny_panic: {
+3 | |         foo(1);
+4 | |     }
+  | |_____^ this 'deny_panic block can reach `core::panicking::panic`
+  |
+note: `a::foo` is called here
+ --> src/lib.rs:3:9
+  |
+3 |         foo(1);
+  |         ^^^
+  |
+note: `core::panicking::panic` is called here
+ --> src/lib.rs:8:5
+  |
+8 |     panic!()
+  |     ^^^^^^^^
+  |
+  = note: `core::panicking::panic` panics
+  = help: if the panic can not happen put the call in an 'allow_panic block, otherwise use a \
+                    non-panicking alternative (eg `get` instead of indexing, `checked_*` arithmetic \
+                    or a match instead of `unwrap`)
+";
+    assert_eq!(rendered, expected);
+}
@@ -0,0 +1,276 @@
+//! JUnit XML report, a test suite for each crate and a test case for each 'deny_panic block.
+//!
+//! A block passes if no panic can be reached from it, otherwise it fails with the call stacks
+//! that lead to the panics. The 'allow_panic blocks reached from a 'deny_panic block are skipped
+//! test cases. The test cases are named after the function and the start of the block, a function
+//! can have more than one block.
+use std::io::Write;
+
+use super::{CallFrame, Report};
+
+pub fn write_junit(report: &Report, out: &mut dyn Write) -> std::io::Result<()> {
+    let mut crates: Vec<&str> = report
+        .deny_blocks
+        .iter()
+        .map(|block| block.crate_name.as_str())
+        .chain(report.violations.iter().map(|v| v.crate_name.as_str()))
+        .chain(report.allow_panics.iter().map(|a| a.crate_name.as_str()))
+        .collect();
+    crates.sort_unstable();
+    crates.dedup();
+    let blocks = deny_blocks(report, None);
+    let allow_blocks = allow_blocks(report, None);
+    let failures = blocks
+        .iter()
+        .filter(|block| {
+            report
+                .violations
+                .iter()
+                .any(|v| same_block(&v.deny_block, block))
+        })
+        .count();
+    writeln!(out, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+    writeln!(
+        out,
+        r#"<testsuites name="unpanic" tests="{}" failures="{}" skipped="{}">"#,
+        blocks.len() + allow_blocks.len(),
+        failures,
+        allow_blocks.len()
+    )?;
+    for crate_name in crates {
+        write_suite(report, crate_name, out)?;
+    }
+    writeln!(out, "</testsuites>")
+}
+
+fn write_suite(report: &Report, crate_name: &str, out: &mut dyn Write) -> std::io::Result<()> {
+    let blocks = deny_blocks(report, Some(crate_name));
+    let allow_blocks = allow_blocks(report, Some(crate_name));
+    let failures = blocks
+        .iter()
+        .filter(|block| {
+            report
+                .violations
+                .iter()
+                .any(|v| same_block(&v.deny_block, block))
+        })
+        .count();
+    writeln!(
+        out,
+        r#"  <testsuite name="{}" tests="{}" failures="{}" skipped="{}">"#,
+        escape(crate_name),
+        blocks.len() + allow_blocks.len(),
+        failures,
+        allow_blocks.len()
+    )?;
+    for block in blocks {
+        write!(
+            out,
+            r#"    <testcase {}"#,
+            test_case_attributes(crate_name, block)
+        )?;
+        let violations: Vec<_> = report
+            .violations
+            .iter()
+            .filter(|violation| same_block(&violation.deny_block, block))
+            .collect();
+        if violations.is_empty() {
+            writeln!(out, "/>")?;
+            continue;
+        }
+        writeln!(out, ">")?;
+        let panics: Vec<String> = violations.iter().map(|v| v.panic.to_string()).collect();
+        write!(
+            out,
+            r#"      <failure message="'deny_panic block can panic: {}" type="deny-panic">"#,
+            escape(&panics.join(", "))
+        )?;
+        for (i, violation) in violations.iter().enumerate() {
+            if i > 0 {
+                writeln!(out)?;
+            }
+            for frame in violation.frames() {
+                let line = match frame.location() {
+                    Some(location) => format!("{} at {}", frame, location),
+                    None => frame.to_string(),
+                };
+                writeln!(out, "{}", escape(&line))?;
+            }
+        }
+        writeln!(out, "</failure>")?;
+        writeln!(out, "    </testcase>")?;
+    }
+    for (allow_block, reached_from) in allow_blocks {
+        writeln!(
+            out,
+            r#"    <testcase {}>"#,
+            test_case_attributes(crate_name, allow_block)
+        )?;
+        let reached_from: Vec<String> = reached_from.iter().map(|f| f.to_string()).collect();
+        writeln!(
+            out,
+            r#"      <skipped message="'allow_panic block reached from {}"/>"#,
+            escape(&reached_from.join(", "))
+        )?;
+        writeln!(out, "    </testcase>")?;
+    }
+    writeln!(out, "  </testsuite>")
+}
+
+/// The frames are the same block if they start at the same place: the second pass of the analysis
+/// builds a frame for a block each time that it finds a closure passed from it
+fn same_block(a: &CallFrame, b: &CallFrame) -> bool {
+    match (a.location(), b.location()) {
+        (Some(a), Some(b)) => (&a.file, a.line, a.column) == (&b.file, b.line, b.column),
+        _ => a == b,
+    }
+}
+
+/// The 'deny_panic blocks of crate_name, or of all the crates if it is None
+fn deny_blocks<'a>(report: &'a Report, crate_name: Option<&str>) -> Vec<&'a CallFrame> {
+    let mut blocks: Vec<&CallFrame> = vec![];
+    for block in &report.deny_blocks {
+        if crate_name.map_or(true, |c| c == block.crate_name)
+            && !blocks.iter().any(|b| same_block(b, block))
+        {
+            blocks.push(block);
+        }
+    }
+    blocks
+}
+
+/// The 'allow_panic blocks of crate_name, or of all the crates if it is None, with the
+/// 'deny_panic blocks they are reached from
+fn allow_blocks<'a>(
+    report: &'a Report,
+    crate_name: Option<&str>,
+) -> Vec<(&'a CallFrame, Vec<&'a CallFrame>)> {
+    let mut blocks: Vec<(&CallFrame, Vec<&CallFrame>)> = vec![];
+    for allow_panic in &report.allow_panics {
+        if crate_name.map_or(false, |c| c != allow_panic.crate_name) {
+            continue;
+        }
+        let allow_block = allow_panic
+            .call_chain
+            .last()
+            .unwrap_or(&allow_panic.deny_block);
+        let deny_block = &allow_panic.deny_block;
+        match blocks.iter_mut().find(|(b, _)| same_block(b, allow_block)) {
+            Some((_, reached_from)) => {
+                if !reached_from.iter().any(|b| same_block(b, deny_block)) {
+                    reached_from.push(deny_block);
+                }
+            }
+            None => blocks.push((allow_block, vec![deny_block])),
+        }
+    }
+    blocks
+}
+
+/// `name`, `classname`, `file` and `line` of the test case for the block that start at frame
+fn test_case_attributes(crate_name: &str, frame: &CallFrame) -> String {
+    let name = match frame.location() {
+        Some(location) => format!(
+            "{} at {}:{}:{}",
+            frame.path, location.file, location.line, location.column
+        ),
+        None => frame.path.clone(),
+    };
+    let mut attributes = format!(
+        r#"name="{}" classname="{}""#,
+        escape(&name),
+        escape(crate_name)
+    );
+    if let Some(location) = frame.location() {
+        attributes.push_str(&format!(
+            r#" file="{}" line="{}""#,
+            escape(&location.file),
+            location.line
+        ));
+    }
+    attributes
+}
+
+fn escape(s: &str) -> String {
+    let mut ret = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => ret.push_str("&amp;"),
+            '<' => ret.push_str("&lt;"),
+            '>' => ret.push_str("&gt;"),
+            '"' => ret.push_str("&quot;"),
+            '\'' => ret.push_str("&apos;"),
+            _ => ret.push(c),
+        }
+    }
+    ret
+}
+
+#[test]
+fn test_write_junit() {
+    use super::{test_frame, AllowPanic, CallKind, Metadata, Violation};
+    let it_panic = test_frame("a::it_panic", CallKind::DenyBlock, "src/lib.rs:12:5: 14:6");
+    // A second block in the same function
+    let it_panic_2 = test_frame("a::it_panic", CallKind::DenyBlock, "src/lib.rs:15:5: 17:6");
+    let it_do_not_panic = test_frame(
+        "a::it_do_not_panic",
+        CallKind::DenyBlock,
+        "src/lib.rs:20:5: 24:6",
+    );
+    let panic = test_frame(
+        "core::panicking::panic",
+        CallKind::Fn,
+        "src/lib.rs:13:9: 13:20",
+    );
+    let allow_block = test_frame("a::foo", CallKind::AllowBlock, "src/lib.rs:32:5: 33:6");
+    let report = Report {
+        version: super::REPORT_VERSION,
+        metadata: Metadata {
+            unpanic_version: "0.1.0".to_string(),
+            rustc_version: "rustc 1.74.0-nightly".to_string(),
+            workspace_root: "/ws".into(),
+            targets: vec!["a".to_string()],
+            analyzed_crates: vec!["a".to_string()],
+            trusted_crates: vec![],
+            skipped_crates: vec![],
+        },
+        // The second pass can build the frame of a block that is already there
+        deny_blocks: vec![
+            it_panic.clone(),
+            it_panic_2.clone(),
+            it_do_not_panic.clone(),
+            it_panic.clone(),
+        ],
+        violations: vec![Violation::from_stack(&[it_panic, panic]).unwrap()],
+        allow_panics: vec![
+            AllowPanic::from_stack(&[it_do_not_panic, allow_block.clone()]).unwrap(),
+            AllowPanic::from_stack(&[it_panic_2, allow_block]).unwrap(),
+        ],
+        unresolved_calls: vec![],
+        baseline: None,
+    };
+    let mut out = vec![];
+    write_junit(&report, &mut out).unwrap();
+    let out = String::from_utf8(out).unwrap();
+    assert!(out.contains(r#"<testsuites name="unpanic" tests="4" failures="1" skipped="1">"#));
+    assert!(out.contains(
+        r#"<testcase name="a::it_panic at src/lib.rs:12:5" classname="a" file="src/lib.rs" line="12">"#
+    ));
+    assert!(
+        out.contains(r#"<failure message="'deny_panic block can panic: `core::panicking::panic`""#)
+    );
+    assert!(out.contains("`core::panicking::panic` at src/lib.rs:13:9: 13:20\n</failure>"));
+    assert!(out.contains(
+        r#"<testcase name="a::it_panic at src/lib.rs:15:5" classname="a" file="src/lib.rs" line="15"/>"#
+    ));
+    assert!(out.contains(
+        r#"<testcase name="a::it_do_not_panic at src/lib.rs:20:5" classname="a" file="src/lib.rs" line="20"/>"#
+    ));
+    assert!(out.contains(
+        r#"<testcase name="a::foo at src/lib.rs:32:5" classname="a" file="src/lib.rs" line="32">"#
+    ));
+    assert!(out.contains(
+        r#"<skipped message="'allow_panic block reached from `a::it_do_not_panic`, `a::it_panic`"/>"#
+    ));
+    assert_eq!(out.matches("<testcase ").count(), 4);
+}
@@ -0,0 +1,489 @@
+//! The result of the analysis and the formats in which it can be written.
+//!
+//! `human` is the default and goes to stderr, the other formats go to stdout or to the file passed
+//! with `--output`.
+use serde::Serialize;
+use std::fmt;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use crate::errors::Error;
+pub use baseline::{Baseline, BaselineSummary};
+
+mod baseline;
+mod diagnostics;
+mod human;
+mod junit;
+mod sarif;
+mod source;
+
+/// Bumped every time the json report change in a way that can break its readers
+pub const REPORT_VERSION: u64 = 2;
+
+/// Exit code when no 'deny_panic block can panic
+pub const EXIT_CLEAN: i32 = 0;
+/// Exit code when a 'deny_panic block can panic, or reaches an 'allow_panic block with
+/// `--fail-on allow-panics`
+pub const EXIT_VIOLATIONS: i32 = 1;
+/// Exit code when the analysis can not be completed: unpanic failed, or a call can not be checked
+/// with `--fail-on unresolved`
+pub const EXIT_INCOMPLETE: i32 = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Human,
+    Json,
+    /// SARIF 2.1.0 for code scanning tools
+    Sarif,
+    /// JUnit XML, a test case for each 'deny_panic block
+    Junit,
+    /// rustc json diagnostics for editors
+    RustcJson,
+}
+
+impl FromStr for Format {
+    type Err = Error;
+
+    fn from_str(format: &str) -> Result<Self, Error> {
+        match format {
+            "human" => Ok(Format::Human),
+            "json" => Ok(Format::Json),
+            "sarif" => Ok(Format::Sarif),
+            "junit" => Ok(Format::Junit),
+            "rustc-json" => Ok(Format::RustcJson),
+            _ => Err(Error::InvalidArgument(format!(
+                "unknown format {}, expected human, json, sarif, junit or rustc-json",
+                format
+            ))),
+        }
+    }
+}
+
+/// What fails the analysis besides the 'deny_panic blocks that can panic, parsed from a comma
+/// separated list, eg `allow-panics,unresolved`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FailOn {
+    /// 'allow_panic blocks reached from a 'deny_panic block
+    pub allow_panics: bool,
+    /// Calls into crates that have not been analyzed
+    pub unresolved: bool,
+}
+
+impl FromStr for FailOn {
+    type Err = Error;
+
+    fn from_str(fail_on: &str) -> Result<Self, Error> {
+        let mut ret = Self::default();
+        for kind in fail_on.split(',').map(str::trim) {
+            match kind {
+                // Always fail on the violations
+                "violations" => (),
+                "allow-panics" => ret.allow_panics = true,
+                "unresolved" => ret.unresolved = true,
+                _ => {
+                    return Err(Error::InvalidArgument(format!(
+                        "unknown --fail-on {}, expected violations, allow-panics or unresolved",
+                        kind
+                    )))
+                }
+            }
+        }
+        Ok(ret)
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Report {
+    /// REPORT_VERSION
+    pub version: u64,
+    pub metadata: Metadata,
+    /// The functions with a 'deny_panic block that have been checked, sorted by crate name
+    pub deny_blocks: Vec<CallFrame>,
+    /// Sorted by crate name
+    pub violations: Vec<Violation>,
+    /// Empty if `allow-panics = false` in unpanic.toml
+    pub allow_panics: Vec<AllowPanic>,
+    /// Sorted by crate name
+    pub unresolved_calls: Vec<UnresolvedCall>,
+    /// None if the report has not been compared with a baseline
+    pub baseline: Option<BaselineSummary>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Metadata {
+    pub unpanic_version: String,
+    pub rustc_version: String,
+    /// Relative paths in the locations are relative to it
+    pub workspace_root: PathBuf,
+    pub targets: Vec<String>,
+    pub analyzed_crates: Vec<String>,
+    pub trusted_crates: Vec<String>,
+    pub skipped_crates: Vec<String>,
+}
+
+/// A 'deny_panic block that can panic
+#[derive(Debug, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Violation {
+    /// Crate of the function that contains the 'deny_panic block
+    pub crate_name: String,
+    /// The function that contains the 'deny_panic block
+    pub deny_block: CallFrame,
+    /// The calls from the 'deny_panic block to the panic
+    pub call_chain: Vec<CallFrame>,
+    /// The call to the panicking function, eg `core::panicking::panic`
+    pub panic: CallFrame,
+}
+
+/// An 'allow_panic block reached from a 'deny_panic block
+#[derive(Debug, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct AllowPanic {
+    /// Crate of the function that contains the 'deny_panic block
+    pub crate_name: String,
+    pub deny_block: CallFrame,
+    /// The calls from the 'deny_panic block to the 'allow_panic block, the last one is the
+    /// 'allow_panic block
+    pub call_chain: Vec<CallFrame>,
+}
+
+/// A call from a 'deny_panic block into a crate that has not been analyzed, eg a crate that has
+/// not been compiled by the wrapper because unpanic is RUSTC_WORKSPACE_WRAPPER
+#[derive(Debug, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct UnresolvedCall {
+    /// Crate of the function that contains the 'deny_panic block
+    pub crate_name: String,
+    pub deny_block: CallFrame,
+    /// The calls from the 'deny_panic block, the last one is the call that can not be checked
+    pub call_chain: Vec<CallFrame>,
+}
+
+/// A frame of a call stack. The frames do not refer to the rustc session in which they have
+/// been found, so that the stacks can go from the analysis of a crate to the analysis of its
+/// dependencies.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct CallFrame {
+    /// Fully qualified path of the function, eg `test1_lib::method_test::MethodTest::method_test`
+    pub path: String,
+    pub crate_name: String,
+    /// Version of the package of the crate, None for the crates not built by cargo
+    pub crate_version: Option<String>,
+    pub kind: CallKind,
+    /// The trait method that has been called, eg `test1_lib::Trait::trait_0`, if the function is
+    /// the implementation of a trait method
+    pub via_trait: Option<String>,
+    /// Where the function is called, None for the first frame of a stack
+    pub caller_span: Option<Location>,
+    /// The function, or the block for the 'deny_panic and 'allow_panic frames
+    pub callee_span: Option<Location>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum CallKind {
+    /// The first frame of a stack: the function that contains the 'deny_panic block or the one
+    /// that pass an argument to a function with a 'deny_panic block
+    DenyBlock,
+    Fn,
+    Method,
+    /// A trait method resolved to its implementation
+    TraitImpl,
+    Closure,
+    /// A closure or a trait implementation passed to a function with a 'deny_panic block
+    HigherOrderArgument,
+    AllowBlock,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Location {
+    pub file: String,
+    pub line: usize,
+    pub column: usize,
+    pub end_line: usize,
+    pub end_column: usize,
+}
+
+impl CallFrame {
+    /// Where the function is called, or the function itself if the call site is unknown
+    pub fn location(&self) -> Option<&Location> {
+        self.caller_span.as_ref().or(self.callee_span.as_ref())
+    }
+}
+
+impl fmt::Display for CallFrame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "`{}`", self.path)?;
+        if let Some(trait_fn) = &self.via_trait {
+            write!(f, " (via trait `{}`)", trait_fn)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}:{}:{}: {}:{}",
+            self.file, self.line, self.column, self.end_line, self.end_column
+        )
+    }
+}
+
+impl Violation {
+    /// The first frame of stack is the 'deny_panic block and the last one the call to the panic
+    pub fn from_stack(stack: &[CallFrame]) -> Option<Self> {
+        let (deny_block, rest) = stack.split_first()?;
+        let (panic, call_chain) = rest.split_last()?;
+        Some(Self {
+            crate_name: deny_block.crate_name.clone(),
+            deny_block: deny_block.clone(),
+            call_chain: call_chain.to_vec(),
+            panic: panic.clone(),
+        })
+    }
+
+    pub fn frames(&self) -> impl Iterator<Item = &CallFrame> {
+        std::iter::once(&self.deny_block)
+            .chain(&self.call_chain)
+            .chain(std::iter::once(&self.panic))
+    }
+
+    pub fn order(&self) -> StackOrder<'_> {
+        stack_order(&self.crate_name, self.frames())
+    }
+}
+
+impl AllowPanic {
+    /// The first frame of stack is the 'deny_panic block and the last one the 'allow_panic block
+    pub fn from_stack(stack: &[CallFrame]) -> Option<Self> {
+        let (deny_block, call_chain) = stack.split_first()?;
+        Some(Self {
+            crate_name: deny_block.crate_name.clone(),
+            deny_block: deny_block.clone(),
+            call_chain: call_chain.to_vec(),
+        })
+    }
+
+    pub fn frames(&self) -> impl Iterator<Item = &CallFrame> {
+        std::iter::once(&self.deny_block).chain(&self.call_chain)
+    }
+
+    pub fn order(&self) -> StackOrder<'_> {
+        stack_order(&self.crate_name, self.frames())
+    }
+}
+
+impl UnresolvedCall {
+    /// The first frame of stack is the 'deny_panic block and the last one the unresolved call
+    pub fn from_stack(stack: &[CallFrame]) -> Option<Self> {
+        let (deny_block, call_chain) = stack.split_first()?;
+        Some(Self {
+            crate_name: deny_block.crate_name.clone(),
+            deny_block: deny_block.clone(),
+            call_chain: call_chain.to_vec(),
+        })
+    }
+
+    pub fn frames(&self) -> impl Iterator<Item = &CallFrame> {
+        std::iter::once(&self.deny_block).chain(&self.call_chain)
+    }
+
+    pub fn order(&self) -> StackOrder<'_> {
+        stack_order(&self.crate_name, self.frames())
+    }
+}
+
+/// The crate, then the location and the path of every frame from the 'deny_panic block
+pub type StackOrder<'a> = (&'a str, Vec<(Option<(&'a str, usize, usize)>, &'a str)>);
+
+/// The violations, the 'allow_panic blocks and the unresolved calls are sorted by crate,
+/// 'deny_panic block and call chain, so that the report does not depend on the order in which the
+/// crates have been analyzed
+fn stack_order<'a>(
+    crate_name: &'a str,
+    frames: impl Iterator<Item = &'a CallFrame>,
+) -> StackOrder<'a> {
+    let frames = frames
+        .map(|frame| {
+            let location = frame
+                .location()
+                .map(|l| (l.file.as_str(), l.line, l.column));
+            (location, frame.path.as_str())
+        })
+        .collect();
+    (crate_name, frames)
+}
+
+impl Report {
+    /// EXIT_VIOLATIONS, EXIT_INCOMPLETE or EXIT_CLEAN
+    pub fn exit_code(&self, fail_on: FailOn) -> i32 {
+        if !self.violations.is_empty() || (fail_on.allow_panics && !self.allow_panics.is_empty()) {
+            EXIT_VIOLATIONS
+        } else if fail_on.unresolved && !self.unresolved_calls.is_empty() {
+            EXIT_INCOMPLETE
+        } else {
+            EXIT_CLEAN
+        }
+    }
+
+    /// Write the report in format to output, if output is None human go to stderr and the other
+    /// formats to stdout
+    pub fn write(&self, format: Format, output: Option<&Path>) -> std::io::Result<()> {
+        let mut out: Box<dyn Write> = match (output, format) {
+            (Some(path), _) => Box::new(std::fs::File::create(path)?),
+            (None, Format::Human) => Box::new(std::io::stderr()),
+            (None, _) => Box::new(std::io::stdout()),
+        };
+        match format {
+            Format::Human => human::write_human(self, &mut out),
+            Format::Json => {
+                serde_json::to_writer_pretty(&mut out, self)?;
+                writeln!(out)
+            }
+            Format::Sarif => {
+                serde_json::to_writer_pretty(&mut out, &sarif::to_sarif(self))?;
+                writeln!(out)
+            }
+            Format::Junit => junit::write_junit(self, &mut out),
+            Format::RustcJson => diagnostics::write_diagnostics(self, &mut out),
+        }
+    }
+}
+
+/// A frame for the tests, path is `crate_name::function` and the span is `file:line:col: line:col`
+#[cfg(test)]
+pub fn test_frame(path: &str, kind: CallKind, span: &str) -> CallFrame {
+    let (file, rest) = span.split_once(':').unwrap();
+    let numbers: Vec<usize> = rest
+        .split([':', ' '])
+        .filter(|n| !n.is_empty())
+        .map(|n| n.parse().unwrap())
+        .collect();
+    let location = Location {
+        file: file.to_string(),
+        line: numbers[0],
+        column: numbers[1],
+        end_line: numbers[2],
+        end_column: numbers[3],
+    };
+    let (caller_span, callee_span) = match kind {
+        CallKind::DenyBlock | CallKind::AllowBlock => (None, Some(location)),
+        _ => (Some(location), None),
+    };
+    CallFrame {
+        path: path.to_string(),
+        crate_name: path.split("::").next().unwrap().to_string(),
+        crate_version: None,
+        kind,
+        via_trait: None,
+        caller_span,
+        callee_span,
+    }
+}
+
+#[test]
+fn test_from_stack() {
+    let stack = vec![
+        test_frame("a::it_panic", CallKind::DenyBlock, "src/lib.rs:12:1: 14:2"),
+        test_frame("a::foo", CallKind::Fn, "src/lib.rs:13:5: 13:10"),
+        test_frame(
+            "core::panicking::panic",
+            CallKind::Fn,
+            "src/lib.rs:3:5: 3:13",
+        ),
+    ];
+    let violation = Violation::from_stack(&stack).unwrap();
+    assert_eq!(violation.crate_name, "a");
+    assert_eq!(violation.call_chain, &stack[1..2]);
+    assert_eq!(violation.panic.to_string(), "`core::panicking::panic`");
+    assert_eq!(Violation::from_stack(&stack[..1]), None);
+    let allow_panic = AllowPanic::from_stack(&stack[..2]).unwrap();
+    assert_eq!(allow_panic.call_chain.len(), 1);
+    let mut frame = stack[1].clone();
+    frame.via_trait = Some("a::Trait::foo".to_string());
+    assert_eq!(frame.to_string(), "`a::foo` (via trait `a::Trait::foo`)");
+    assert_eq!(frame.location().unwrap().line, 13);
+}
+
+#[test]
+fn test_order() {
+    let violation = |deny_line: usize, call: &str, call_line: usize| {
+        let deny_span = format!("src/lib.rs:{}:1: {}:2", deny_line, deny_line + 2);
+        let call_span = format!("src/lib.rs:{}:5: {}:10", call_line, call_line);
+        Violation::from_stack(&[
+            test_frame("a::it_panic", CallKind::DenyBlock, &deny_span),
+            test_frame(call, CallKind::Fn, &call_span),
+            test_frame(
+                "core::panicking::panic",
+                CallKind::Fn,
+                "src/dep.rs:3:5: 3:13",
+            ),
+        ])
+        .unwrap()
+    };
+    let mut violations = vec![
+        violation(30, "a::f", 31),
+        violation(12, "a::g", 13),
+        violation(12, "a::f", 13),
+        violation(12, "a::f", 9),
+        violation(2, "a::f", 40),
+    ];
+    violations.sort_by(|a, b| a.order().cmp(&b.order()));
+    let order: Vec<(usize, &str, usize)> = violations
+        .iter()
+        .map(|v| {
+            let call = &v.call_chain[0];
+            let line = |frame: &CallFrame| frame.location().unwrap().line;
+            (line(&v.deny_block), call.path.as_str(), line(call))
+        })
+        .collect();
+    assert_eq!(
+        order,
+        [
+            (2, "a::f", 40),
+            (12, "a::f", 9),
+            (12, "a::f", 13),
+            (12, "a::g", 13),
+            (30, "a::f", 31)
+        ]
+    );
+}
+
+#[test]
+fn test_exit_code() {
+    let stack = vec![
+        test_frame("a::it_panic", CallKind::DenyBlock, "src/lib.rs:12:1: 14:2"),
+        test_frame("b::foo", CallKind::Fn, "src/lib.rs:13:5: 13:10"),
+    ];
+    let mut report = Report {
+        version: REPORT_VERSION,
+        metadata: Metadata {
+            unpanic_version: "0.1.0".to_string(),
+            rustc_version: "rustc 1.74.0-nightly".to_string(),
+            workspace_root: "/ws".into(),
+            targets: vec!["a".to_string()],
+            analyzed_crates: vec!["a".to_string()],
+            trusted_crates: vec![],
+            skipped_crates: vec![],
+        },
+        deny_blocks: vec![stack[0].clone()],
+        violations: vec![],
+        allow_panics: vec![],
+        unresolved_calls: vec![UnresolvedCall::from_stack(&stack).unwrap()],
+        baseline: None,
+    };
+    let fail_on: FailOn = "violations".parse().unwrap();
+    assert_eq!(report.exit_code(fail_on), EXIT_CLEAN);
+    let fail_on: FailOn = "allow-panics, unresolved".parse().unwrap();
+    assert_eq!(report.exit_code(fail_on), EXIT_INCOMPLETE);
+    report.violations = vec![Violation::from_stack(&stack).unwrap()];
+    assert_eq!(report.exit_code(fail_on), EXIT_VIOLATIONS);
+    assert!("unresolved,panics".parse::<FailOn>().is_err());
+}
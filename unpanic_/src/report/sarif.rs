@@ -0,0 +1,210 @@
+//! SARIF 2.1.0 report, every violation is a result with a code flow that goes from the
+//! 'deny_panic block to the panic, so that code scanning tools can show the whole call chain.
+//!
+//! Locations inside the workspace root are relative to `%SRCROOT%`. Relative paths are the ones
+//! passed to rustc by cargo for the workspace members, so they are already relative to the
+//! workspace root.
+use serde_json::{json, Value};
+use std::path::Path;
+
+use super::{Location, Report, Violation};
+
+const SCHEMA: &str = "https://json.schemastore.org/sarif-2.1.0.json";
+const SRCROOT: &str = "%SRCROOT%";
+
+pub fn to_sarif(report: &Report) -> Value {
+    let workspace_root = &report.metadata.workspace_root;
+    // (rule id, panicking function)
+    let mut rules: Vec<(String, String)> = vec![];
+    let mut results = vec![];
+    for violation in &report.violations {
+        let panic = violation.panic.to_string();
+        let rule_id = rule_id(&violation.panic.path);
+        let rule_index = match rules.iter().position(|(id, _)| *id == rule_id) {
+            Some(index) => index,
+            None => {
+                rules.push((rule_id.clone(), panic));
+                rules.len() - 1
+            }
+        };
+        results.push(result(violation, &rule_id, rule_index, workspace_root));
+    }
+    let rules: Vec<Value> = rules
+        .iter()
+        .map(|(id, panic)| {
+            json!({
+                "id": id,
+                "shortDescription": {
+                    "text": format!("'deny_panic block can call {}", panic),
+                },
+                "defaultConfiguration": { "level": "error" },
+            })
+        })
+        .collect();
+    json!({
+        "$schema": SCHEMA,
+        "version": "2.1.0",
+        "runs": [{
+            "tool": {
+                "driver": {
+                    "name": "unpanic",
+                    "version": report.metadata.unpanic_version,
+                    "rules": rules,
+                },
+            },
+            "originalUriBaseIds": {
+                SRCROOT: { "uri": format!("{}/", file_uri(workspace_root)) },
+            },
+            "results": results,
+        }],
+    })
+}
+
+/// One rule per panicking function (the panic kind), eg `deny-panic/core/panicking/panic`
+fn rule_id(panic: &str) -> String {
+    format!("deny-panic/{}", panic.replace("::", "/"))
+}
+
+fn result(violation: &Violation, rule_id: &str, rule_index: usize, workspace_root: &Path) -> Value {
+    let via: Vec<String> = std::iter::once(&violation.deny_block)
+        .chain(&violation.call_chain)
+        .map(ToString::to_string)
+        .collect();
+    let message = format!(
+        "this 'deny_panic block can reach {} via {}",
+        violation.panic,
+        via.join(" → ")
+    );
+    // The deny block, or the first frame with a location if it has none
+    let location = violation
+        .frames()
+        .find_map(|frame| frame.location())
+        .map(|location| json!({ "physicalLocation": physical_location(location, workspace_root) }));
+    let flow: Vec<Value> = violation
+        .frames()
+        .map(|frame| {
+            let mut location = json!({ "message": { "text": frame.to_string() } });
+            if let Some(frame_location) = frame.location() {
+                location["physicalLocation"] = physical_location(frame_location, workspace_root);
+            }
+            json!({ "location": location })
+        })
+        .collect();
+    json!({
+        "ruleId": rule_id,
+        "ruleIndex": rule_index,
+        "level": "error",
+        "message": { "text": message },
+        "locations": location.into_iter().collect::<Vec<_>>(),
+        "codeFlows": [{ "threadFlows": [{ "locations": flow }] }],
+    })
+}
+
+fn physical_location(location: &Location, workspace_root: &Path) -> Value {
+    let path = Path::new(&location.file);
+    let artifact_location = match path.strip_prefix(workspace_root) {
+        Ok(relative) => json!({ "uri": encode(relative), "uriBaseId": SRCROOT }),
+        Err(_) if path.is_relative() => json!({ "uri": encode(path), "uriBaseId": SRCROOT }),
+        Err(_) => json!({ "uri": file_uri(path) }),
+    };
+    json!({
+        "artifactLocation": artifact_location,
+        "region": {
+            "startLine": location.line,
+            "startColumn": location.column,
+            "endLine": location.end_line,
+            "endColumn": location.end_column,
+        },
+    })
+}
+
+fn file_uri(path: &Path) -> String {
+    let path = encode(path);
+    if path.starts_with('/') {
+        format!("file://{}", path)
+    } else {
+        // Windows paths, eg C:/
+        format!("file:///{}", path)
+    }
+}
+
+/// Percent-encode path as an uri path, with `/` as separator
+fn encode(path: &Path) -> String {
+    let mut ret = String::new();
+    for byte in path.to_string_lossy().replace('\\', "/").bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' | b':' => {
+                ret.push(byte as char)
+            }
+            _ => ret.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    ret
+}
+
+#[test]
+fn test_to_sarif() {
+    use super::{test_frame, CallKind, Metadata};
+    let stack = vec![
+        test_frame(
+            "test2_lib::it_panic",
+            CallKind::DenyBlock,
+            "/ws/tests/test2-lib/src/lib.rs:12:1: 14:2",
+        ),
+        test_frame(
+            "dep::foo",
+            CallKind::Fn,
+            "/ws/tests/test2-lib/src/lib.rs:13:5: 13:20",
+        ),
+        test_frame(
+            "core::panicking::panic",
+            CallKind::Fn,
+            "/home/me/.cargo/registry/src/a b/lib.rs:3:5: 3:20",
+        ),
+    ];
+    let violation = Violation::from_stack(&stack).unwrap();
+    let report = Report {
+        version: super::REPORT_VERSION,
+        metadata: Metadata {
+            unpanic_version: "0.1.0".to_string(),
+            rustc_version: "rustc 1.74.0-nightly".to_string(),
+            workspace_root: "/ws".into(),
+            targets: vec!["test2_lib".to_string()],
+            analyzed_crates: vec!["test2_lib".to_string()],
+            trusted_crates: vec![],
+            skipped_crates: vec![],
+        },
+        deny_blocks: vec![],
+        violations: vec![violation],
+        allow_panics: vec![],
+        unresolved_calls: vec![],
+        baseline: None,
+    };
+    let sarif = to_sarif(&report);
+    let run = &sarif["runs"][0];
+    assert_eq!(run["originalUriBaseIds"][SRCROOT]["uri"], "file:///ws/");
+    assert_eq!(
+        run["tool"]["driver"]["rules"][0]["id"],
+        "deny-panic/core/panicking/panic"
+    );
+    let result = &run["results"][0];
+    assert_eq!(result["ruleId"], "deny-panic/core/panicking/panic");
+    assert_eq!(
+        result["message"]["text"],
+        "this 'deny_panic block can reach `core::panicking::panic` via `test2_lib::it_panic` → \
+         `dep::foo`"
+    );
+    let artifact = &result["locations"][0]["physicalLocation"]["artifactLocation"];
+    assert_eq!(artifact["uri"], "tests/test2-lib/src/lib.rs");
+    assert_eq!(artifact["uriBaseId"], SRCROOT);
+    let flow = &result["codeFlows"][0]["threadFlows"][0]["locations"];
+    assert_eq!(flow.as_array().unwrap().len(), 3);
+    assert_eq!(
+        flow[2]["location"]["physicalLocation"]["artifactLocation"]["uri"],
+        "file:///home/me/.cargo/registry/src/a%20b/lib.rs"
+    );
+    assert_eq!(
+        flow[2]["location"]["message"]["text"],
+        "`core::panicking::panic`"
+    );
+}
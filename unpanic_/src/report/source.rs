@@ -0,0 +1,80 @@
+//! Read the source code of the locations in the report, relative paths are relative to the
+//! workspace root.
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use super::Location;
+
+pub struct Sources<'a> {
+    workspace_root: &'a Path,
+    /// None if the file can not be read
+    files: HashMap<PathBuf, Option<String>>,
+}
+
+impl<'a> Sources<'a> {
+    pub fn new(workspace_root: &'a Path) -> Self {
+        Self {
+            workspace_root,
+            files: HashMap::new(),
+        }
+    }
+
+    fn source(&mut self, location: &Location) -> Option<&str> {
+        let path = self.workspace_root.join(&location.file);
+        self.files
+            .entry(path)
+            .or_insert_with_key(|path| std::fs::read_to_string(path).ok())
+            .as_deref()
+    }
+
+    /// The lines from location.line to location.end_line
+    pub fn lines(&mut self, location: &Location) -> Option<Vec<String>> {
+        let source = self.source(location)?;
+        let lines: Vec<String> = source
+            .lines()
+            .skip(location.line.checked_sub(1)?)
+            .take(location.end_line.checked_sub(location.line)? + 1)
+            .map(String::from)
+            .collect();
+        if lines.len() == location.end_line - location.line + 1 {
+            Some(lines)
+        } else {
+            None
+        }
+    }
+
+    /// Byte offsets of the start and of the end of location, the columns are 1-based and count
+    /// chars like the ones of rustc
+    pub fn byte_range(&mut self, location: &Location) -> Option<(usize, usize)> {
+        let source = self.source(location)?;
+        let start = byte_offset(source, location.line, location.column)?;
+        let end = byte_offset(source, location.end_line, location.end_column)?;
+        Some((start, end))
+    }
+}
+
+fn byte_offset(source: &str, line: usize, column: usize) -> Option<usize> {
+    let mut lines = source.split_inclusive('\n');
+    let line_start: usize = lines
+        .by_ref()
+        .take(line.checked_sub(1)?)
+        .map(str::len)
+        .sum();
+    let line = lines.next()?;
+    let column: usize = line
+        .chars()
+        .take(column.checked_sub(1)?)
+        .map(char::len_utf8)
+        .sum();
+    Some(line_start + column)
+}
+
+#[test]
+fn test_byte_offset() {
+    let source = "fn main() {\n    let é = 1;\n}\n";
+    assert_eq!(byte_offset(source, 1, 1), Some(0));
+    assert_eq!(byte_offset(source, 2, 5), Some(16));
+    // After the multi byte char
+    assert_eq!(byte_offset(source, 2, 10), Some(22));
+    assert_eq!(byte_offset(source, 4, 1), None);
+}
@@ -1,146 +1,14 @@
-use alloc::collections::{btree_map::BTreeMap, btree_set::BTreeSet};
-use rustc_session::{
-    config::{ExternEntry, ExternLocation, Externs},
-    search_paths::{PathKind, SearchPath, SearchPathFile},
-    utils::CanonicalizedPath,
-};
 use rustc_span::edition::Edition;
 use std::path::{Path, PathBuf};
 
 use crate::errors::Error;
 use std::str::FromStr;
 
-/// Get the args from cargo and return the path for the sources
-pub fn get_location(args: &[String]) -> Result<String, Error> {
-    let mut args = args.iter();
-    if args.any(|s| s == "--crate-name") {
-        args.next().ok_or(Error::SrcLocationMissing)?;
-        let possible_location = args.next().ok_or(Error::SrcLocationMissing)?;
-        if &possible_location[..2] == "--" {
-            args.next().ok_or(Error::SrcLocationMissing).cloned()
-        } else {
-            Ok(possible_location.clone())
-        }
-    } else {
-        Err(Error::SrcLocationMissing)
-    }
-}
-
-pub fn get_externs(args: &Vec<String>) -> (Externs, Vec<SearchPath>) {
-    // Just here to check that requested extrns actually exist
-    get_externs_from_args(args);
-    // Return all the compiled libs
-    get_externs_from_fs(args)
-}
-
-pub fn get_externs_from_args(args: &Vec<String>) -> (Externs, Vec<SearchPath>) {
-    let path_dir = get_dep_path(args);
-    let mut argss = "".to_string();
-    for arg in args {
-        argss.push(' ');
-        argss.push_str(arg);
-    }
-    let mut dep_map = BTreeMap::new();
-    let externs_arg = _get_externs(args);
-    let mut search_path = SearchPath {
-        kind: PathKind::All,
-        dir: PathBuf::from_str(&path_dir).expect("ERROR: Invalid path_dir"),
-        files: vec![],
-    };
-    for arg in externs_arg {
-        if arg == "proc_macro" {
-            // TODO fix it
-            continue;
-        }
-        let splitted = arg
-            .split('=')
-            .next_chunk::<2>()
-            .expect("ERROR: Invalid args format");
-        let name = splitted[0];
-        let path_str = splitted[1];
-
-        let path = Path::new(&path_str);
-
-        let file = SearchPathFile {
-            path: path.to_path_buf(),
-            file_name_str: path
-                .file_name()
-                .expect("ERROR: No file name in path")
-                .to_str()
-                .expect("ERROR: Invalid file name in path")
-                .to_string(),
-        };
-        search_path.files.push(file);
-
-        dep_map.insert(
-            name.to_string(),
-            ExternEntry {
-                location: ExternLocation::ExactPaths(BTreeSet::from([CanonicalizedPath::new(
-                    path,
-                )])),
-                is_private_dep: false,
-                add_prelude: true,
-                nounused_dep: false,
-                force: false,
-            },
-        );
-    }
-    let externs = Externs::new(dep_map);
-    (externs, vec![search_path])
-}
-
-pub fn get_externs_from_fs(args: &[String]) -> (Externs, Vec<SearchPath>) {
-    let path_dir = get_dep_path(args);
-    let mut dep_map = BTreeMap::new();
-    let compiled_libs = get_compiled_libs(&path_dir);
-    let mut search_path = SearchPath {
-        kind: PathKind::All,
-        dir: PathBuf::from_str(&path_dir).expect("ERROR: Invalid path_dir"),
-        files: vec![],
-    };
-    for path_str in compiled_libs {
-        let name = name_from_path_string(&path_str);
-        let path_str = path_dir.clone() + "/" + &path_str;
-
-        let path = Path::new(&path_str);
-
-        let file = SearchPathFile {
-            path: path.to_path_buf(),
-            file_name_str: path
-                .file_name()
-                .expect("ERROR: No file name in path")
-                .to_str()
-                .expect("ERROR: Invalid file name in path")
-                .to_string(),
-        };
-        search_path.files.push(file);
-
-        dep_map.insert(
-            name.to_string(),
-            ExternEntry {
-                location: ExternLocation::ExactPaths(BTreeSet::from([CanonicalizedPath::new(
-                    path,
-                )])),
-                is_private_dep: false,
-                add_prelude: true,
-                nounused_dep: false,
-                force: false,
-            },
-        );
-    }
-    let externs = Externs::new(dep_map);
-    (externs, vec![search_path])
-}
-
 fn get_arg(args: &[String], arg_name: &str) -> Vec<String> {
     args.iter()
         .filter(|s| s.contains(arg_name) && *s != arg_name)
-        .map(|s| {
-            s.split('=')
-                .next_chunk::<2>()
-                .expect(format!("ERROR: Can not get {} in args", arg_name).as_str())[1]
-                .to_string()
-        })
+        .filter_map(|s| s.split_once('='))
+        .map(|(_, value)| value.to_string())
         .collect()
 }
 pub fn have_arg(args: &Vec<String>, arg_name: &str) -> bool {
@@ -152,91 +20,35 @@ pub fn have_arg(args: &Vec<String>, arg_name: &str) -> bool {
     false
 }
 
-pub fn get_edition(args: &[String]) -> Edition {
-    let edition = get_arg(args, "--edition=");
-    match edition.len() {
-        // TODO TODO TODO get the default value from a config file
-        0 => Edition::Edition2021,
-        1 => match edition[0].as_str() {
-            "2015" => Edition::Edition2015,
-            "2018" => Edition::Edition2018,
-            "2021" => Edition::Edition2021,
-            _ => panic!("Unsupported edition {}", edition[0].as_str()),
-        },
-        2 => {
-                if edition[0] == edition[1] {
-                    match edition[0].as_str() {
-                        "2015" => Edition::Edition2015,
-                        "2018" => Edition::Edition2018,
-                        "2021" => Edition::Edition2021,
-                        _ => panic!("Unsupported edition {}", edition[0].as_str()),
-                    }
-                } else {
-                    panic!("Can not use more then one edition {:#?}", args);
-                }
-        },
-        _ => panic!("Can not use more then one edition {:#?}", args),
-    }
-}
-
-fn get_dep_path(args: &[String]) -> String {
-    let paths = get_arg(args, "dependency");
-    match paths.len() {
-        1 => paths[0].clone(),
-        2 => {
-            if paths[0] == paths[1] {
-                paths[0].clone()
-            } else {
-                dbg!(args);
-                panic!()
-            }
-        },
-        _ => {
-            dbg!(args);
-            panic!()
-        },
-    }
-}
-
-fn _get_externs(args: &[String]) -> Vec<String> {
-    let mut externs = vec![];
+/// Edition in args, None if args do not contain one.
+/// Every edition known by rustc is supported.
+pub fn get_edition(args: &[String]) -> Result<Option<Edition>, Error> {
+    let mut editions = get_arg(args, "--edition=");
     for (i, arg) in args.iter().enumerate() {
-        if arg == "--extern" {
-            externs.push(args[i + 1].clone());
+        if arg == "--edition" {
+            editions.extend(args.get(i + 1).cloned());
         }
     }
-    externs
-}
-
-/// Return all rmeta file for lib that have been compiled before target
-fn get_compiled_libs(path: &String) -> Vec<String> {
-    std::fs::read_dir(path)
-        .unwrap_or_else(|_| panic!("dependecy path do not exist: {}", path))
-        .map(|path| {
-            path.expect("direntry errror")
-                .file_name()
-                .into_string()
-                .expect("osstring error")
-        })
-        .filter(|path| path.contains(".rmeta") || path.contains(".so"))
-        .collect()
-}
-
-/// Given an rmeta file return the crate name for that file
-fn name_from_path_string(path: &String) -> String {
-    let first_part = path
-        .split('-')
-        .next()
-        .unwrap_or_else(|| panic!("Invalid rmeta file name, should contain -: {}", path));
-    first_part[3..].to_string()
+    editions.sort();
+    editions.dedup();
+    match editions.as_slice() {
+        [] => Ok(None),
+        [edition] => Edition::from_str(edition)
+            .map(Some)
+            .map_err(|_| Error::UnsupportedEdition(edition.clone())),
+        _ => Err(Error::MultipleEditions(editions.clone())),
+    }
 }
 
 #[test]
-fn test_name_from_path_string() {
-    let name = "libinterface_nested_lib-53b4ac4b97d7f3ac.rmeta".to_string();
-    let expected = "interface_nested_lib".to_string();
-    let actual = name_from_path_string(&name);
-    assert_eq!(expected, actual);
+fn test_get_edition() {
+    let args = vec!["--edition=2024".to_string()];
+    assert_eq!(get_edition(&args).unwrap(), Some(Edition::Edition2024));
+    let args = vec!["--edition".to_string(), "2018".to_string()];
+    assert_eq!(get_edition(&args).unwrap(), Some(Edition::Edition2018));
+    assert_eq!(get_edition(&[]).unwrap(), None);
+    let args = vec!["--edition=2015".to_string(), "--edition=2021".to_string()];
+    assert!(get_edition(&args).is_err());
 }
 
 pub fn get_crate_name(args: &[String]) -> Result<String, Error> {
@@ -248,11 +60,48 @@ pub fn get_crate_name(args: &[String]) -> Result<String, Error> {
     }
 }
 
-pub fn is_dependency(args: &[String]) -> bool {
-    let target_crate = std::env::var("TARGET_CRATE").expect("ERROR: Env var TARGET_CRATE not set");
-    let target_crate = target_crate.replace('-', "_");
-    let crate_name = get_crate_name(args).expect("ERROR: Can not get crate name");
-    target_crate != crate_name
+/// Cargo call the wrapper as `unpanic <rustc> args..`, when RUSTC_WORKSPACE_WRAPPER is also set
+/// it call `unpanic <workspace wrapper> <rustc> args..`. `cargo unpanic` move the RUSTC_WRAPPER
+/// that was already set (eg sccache) in UNPANIC_RUSTC_WRAPPER, unless unpanic is
+/// RUSTC_WORKSPACE_WRAPPER: then cargo call `<wrapper> unpanic <rustc> args..`.
+/// Return the inner wrappers, in the order in which they must be called, and the args without
+/// them.
+pub fn split_inner_wrappers(args: &[String]) -> (Vec<String>, Vec<String>) {
+    let is_rustc = |arg: &String| {
+        Path::new(arg)
+            .file_stem()
+            .map_or(false, |stem| stem.to_string_lossy().starts_with("rustc"))
+    };
+    let mut wrappers: Vec<String> = std::env::var("UNPANIC_RUSTC_WRAPPER")
+        .ok()
+        .filter(|wrapper| !wrapper.is_empty())
+        .into_iter()
+        .collect();
+    let mut args = args.to_vec();
+    if args.len() > 2 && !is_rustc(&args[1]) && is_rustc(&args[2]) {
+        wrappers.push(args.remove(1));
+    }
+    (wrappers, args)
+}
+
+#[test]
+fn test_split_inner_wrappers() {
+    let args: Vec<String> = [
+        "unpanic",
+        "/bin/clippy-driver",
+        "/bin/rustc",
+        "--crate-name",
+        "a",
+    ]
+    .iter()
+    .map(|s| s.to_string())
+    .collect();
+    let (wrappers, rustc_args) = split_inner_wrappers(&args);
+    assert_eq!(wrappers.last().unwrap(), "/bin/clippy-driver");
+    assert_eq!(rustc_args[1], "/bin/rustc");
+    let (wrappers, rustc_args) = split_inner_wrappers(&rustc_args);
+    assert!(!wrappers.contains(&"/bin/clippy-driver".to_string()));
+    assert_eq!(rustc_args, ["unpanic", "/bin/rustc", "--crate-name", "a"]);
 }
 
 pub fn get_target_path_index(args: &Vec<String>) -> Result<usize, Error> {
@@ -266,29 +115,88 @@ pub fn get_target_path_index(args: &Vec<String>) -> Result<usize, Error> {
         Err(Error::TargetPathMissing)
     }
 }
-fn is_valid_out_dir(_out_dir: &str) -> bool {
-    true
+/// Directory where the manifest is saved: UNPANIC_DIR if set (`cargo unpanic` always set it),
+/// otherwise `no-panic` in the cargo target dir that contains --out-dir. The out dir can be
+/// anywhere under the target dir (custom profiles, `--target`, build scripts) so the target dir is
+/// found looking for the CACHEDIR.TAG that cargo write in it.
+pub fn get_unpanic_dir(args: &[String], index: usize) -> Result<PathBuf, Error> {
+    if let Some(unpanic_dir) = std::env::var_os("UNPANIC_DIR") {
+        return Ok(PathBuf::from(unpanic_dir));
+    }
+    let out_dir = args.get(index).ok_or(Error::TargetPathMissing)?;
+    if let Some(target_dir) = get_cargo_target_dir(Path::new(out_dir)) {
+        return Ok(target_dir.join("no-panic"));
+    }
+    match std::env::var_os("CARGO_TARGET_DIR").map(PathBuf::from) {
+        Some(target_dir) if target_dir.is_absolute() => Ok(target_dir.join("no-panic")),
+        _ => Err(Error::InvalidOutDir),
+    }
+}
+
+fn get_cargo_target_dir(out_dir: &Path) -> Option<PathBuf> {
+    out_dir
+        .ancestors()
+        .find(|dir| dir.join("CACHEDIR.TAG").is_file())
+        .map(|dir| dir.to_path_buf())
 }
 
-// Get the path where we save dependecy name and rustc arg for that crate
-pub fn get_unpanic_path(args: &[String], index: usize) -> Result<String, Error> {
-    let out_dir = &args[index];
-    if is_valid_out_dir(out_dir) {
-        let mut splitted = out_dir.split("target/");
-        // already checked for path validity
-        let mut head = splitted
-            .next()
-            .expect("ERROR: Can not split out dir")
-            .to_string();
-        let tail = splitted.next().expect("ERROR: Can not split out dir");
-        head.push_str("target/no-panic/");
-        head.push_str(tail);
-        head.remove_matches("/debug");
-        head.remove_matches("/release");
-        head.remove_matches("/build");
-        head.remove_matches("/x86_64-unknown-linux-gnu");
-        Ok(head)
-    } else {
-        Err(Error::InvalidOutDir)
+#[test]
+fn test_get_cargo_target_dir() {
+    let root = std::env::temp_dir().join(format!("unpanic-target-dir-{}", std::process::id()));
+    // The workspace path contains target/ and /build, the target dir is not called target
+    let target_dir = root.join("target/build/ws/ci-build");
+    let out_dirs = [
+        "debug/deps",
+        "ci-fast/deps",
+        "thumbv7em-none-eabihf/release/deps",
+        "debug/build/test2-lib-53b4ac4b97d7f3ac/out",
+    ];
+    for out_dir in out_dirs {
+        std::fs::create_dir_all(target_dir.join(out_dir)).unwrap();
     }
+    std::fs::write(target_dir.join("CACHEDIR.TAG"), "").unwrap();
+    for out_dir in out_dirs {
+        assert_eq!(
+            get_cargo_target_dir(&target_dir.join(out_dir)),
+            Some(target_dir.clone())
+        );
+    }
+    std::fs::remove_dir_all(root).unwrap();
+}
+
+/// Value of the codegen option name in args, passed as `-C name=value`, `-Cname=value` or
+/// `--codegen name=value`
+pub fn get_codegen_opt(args: &[String], name: &str) -> Option<String> {
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let opt = match arg.as_str() {
+            "-C" | "--codegen" => args.next().map(|s| s.as_str()),
+            _ => arg
+                .strip_prefix("-C")
+                .or_else(|| arg.strip_prefix("--codegen=")),
+        };
+        if let Some((key, value)) = opt.and_then(|opt| opt.split_once('=')) {
+            if key == name {
+                return Some(value.to_string());
+            }
+        }
+    }
+    None
+}
+
+#[test]
+fn test_get_codegen_opt() {
+    let args: Vec<String> = ["-C", "extra-filename=-53b4ac4b", "-Cmetadata=53b4ac4b"]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+    assert_eq!(
+        get_codegen_opt(&args, "extra-filename").as_deref(),
+        Some("-53b4ac4b")
+    );
+    assert_eq!(
+        get_codegen_opt(&args, "metadata").as_deref(),
+        Some("53b4ac4b")
+    );
+    assert_eq!(get_codegen_opt(&args, "opt-level"), None);
 }
@@ -1,33 +1,63 @@
 use rustc_driver::DEFAULT_LOCALE_RESOURCES;
 use rustc_errors::registry::Registry;
-use rustc_hash::{FxHashMap, FxHashSet};
+use rustc_hash::FxHashMap;
+use rustc_interface::interface::{parse_cfgspecs, parse_check_cfg};
 use rustc_interface::Config;
 use rustc_session::config::*;
-use rustc_target::spec::TargetTriple;
-use std::path::Path;
+use rustc_session::EarlyErrorHandler;
+use rustc_span::edition::DEFAULT_EDITION;
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+use std::process::Command;
 
+use crate::dep_handler::CrateEntry;
+use crate::dep_handler::Manifest;
+use crate::errors::Error;
 use crate::rustc_arg_handlers::*;
 
-/// Given cargo args create a Config for run_compiler
-pub fn config_from_args(args: &Vec<String>, sysroot: &Path) -> Config {
-    let src_path = &get_location(args).expect("ERROR: No location in args");
-    let src_path = Path::new(src_path);
-    let (externs, search_paths) = get_externs(args);
-    let edition = get_edition(args);
-    Config {
-        opts: Options {
-            maybe_sysroot: Some(sysroot.to_path_buf()),
-            incremental: None,
-            externs,
-            edition,
-            search_paths,
-            target_triple: TargetTriple::TargetTriple("x86_64-unknown-linux-gnu".to_string()),
-            crate_name: Some(get_crate_name(args).expect("ERROR: No crate name in args")),
-            ..Options::default()
-        },
-        input: Input::File(src_path.to_path_buf()),
-        crate_cfg: FxHashSet::default(),
-        crate_check_cfg: CheckCfg::default(),
+/// Given cargo args create a Config for run_compiler.
+/// The args are parsed by rustc itself so that the analyzed crate is the one that has been
+/// compiled: same cfgs (features), check-cfgs, target, codegen and unstable flags, and the same
+/// externs (`--extern name=path` with its `noprelude:`/`priv:` modifiers and sysroot crates like
+/// `proc_macro`) and search paths that cargo passed.
+pub fn config_from_args(args: &[String], sysroot: &Path) -> Result<Config, Error> {
+    let crate_name = get_crate_name(args)?;
+    let invalid = |reason: &str| Error::InvalidRustcArgs {
+        crate_name: crate_name.clone(),
+        reason: reason.to_string(),
+    };
+    let mut handler = EarlyErrorHandler::new(ErrorOutputType::default());
+    // args[0] is unpanic and args[1] is rustc
+    let matches = rustc_driver::handle_options(&handler, args.get(2..).unwrap_or_default())
+        .ok_or_else(|| invalid("rustc can not parse them"))?;
+    let mut opts = build_session_options(&mut handler, &matches);
+    let crate_cfg = parse_cfgspecs(&handler, matches.opt_strs("cfg"));
+    let crate_check_cfg = parse_check_cfg(&handler, matches.opt_strs("check-cfg"));
+    let src_path = matches
+        .free
+        .first()
+        .ok_or_else(|| invalid("no input file"))?;
+
+    // An explicit --sysroot in the args is the one the crate has been compiled with
+    if opts.maybe_sysroot.is_none() {
+        opts.maybe_sysroot = Some(sysroot.to_path_buf());
+    }
+    // Only the hir is needed, nothing is written to disk. Cargo ask for the dep-info that rustc
+    // would write in the current directory, without the --out-dir
+    opts.incremental = None;
+    opts.output_types = OutputTypes::new(&[(OutputType::Metadata, None)]);
+    // Cargo ask for json diagnostics, we want them human readable
+    opts.error_format = ErrorOutputType::default();
+    opts.json_artifact_notifications = false;
+    opts.json_future_incompat = false;
+    // Cargo do not pass --edition for 2015 crates, rustc has compiled them with its default edition
+    opts.edition = get_edition(args)?.unwrap_or(DEFAULT_EDITION);
+
+    Ok(Config {
+        opts,
+        input: Input::File(PathBuf::from(src_path)),
+        crate_cfg,
+        crate_check_cfg,
         output_dir: None,
         output_file: None,
         file_loader: None,
@@ -39,19 +69,148 @@ pub fn config_from_args(args: &Vec<String>, sysroot: &Path) -> Config {
         make_codegen_backend: None,
         registry: Registry::new(rustc_error_codes::DIAGNOSTICS),
         ice_file: None,
+    })
+}
+
+/// Version of the rustc that unpanic is linked to, eg `1.74.0-nightly (58eefc33a 2023-08-24)`
+pub fn rustc_version() -> &'static str {
+    rustc_interface::util::rustc_version_str().unwrap_or("unknown")
+}
+
+/// Version of the rustc executable at path
+pub fn get_rustc_version(rustc: &OsStr) -> Result<String, Error> {
+    let out = Command::new(rustc)
+        .arg("-V")
+        .output()
+        .map_err(|e| Error::InvalidSysroot(format!("can not run {:?}: {}", rustc, e)))?;
+    let version = String::from_utf8_lossy(&out.stdout);
+    let version = version.trim();
+    Ok(version
+        .strip_prefix("rustc ")
+        .unwrap_or(version)
+        .to_string())
+}
+
+/// Sysroot used to analyze the crates: SYSROOT if set, otherwise the sysroot of RUSTC or of the
+/// rustup toolchain in RUSTUP_TOOLCHAIN if set, otherwise the sysroot of the rustc unpanic is
+/// linked to. A `--sysroot` in the rustc args of a crate take precedence (see config_from_args).
+/// The crates are analyzed by the rustc unpanic is linked to so the sysroot must have the same
+/// version.
+pub fn get_sysroot() -> Result<PathBuf, Error> {
+    let sysroot = if let Some(sysroot) = std::env::var_os("SYSROOT") {
+        PathBuf::from(sysroot)
+    } else if std::env::var_os("RUSTC").is_some() || std::env::var_os("RUSTUP_TOOLCHAIN").is_some()
+    {
+        // The rustup proxy select the toolchain in RUSTUP_TOOLCHAIN
+        let rustc = std::env::var_os("RUSTC").unwrap_or_else(|| "rustc".into());
+        let out = Command::new(&rustc)
+            .arg("--print=sysroot")
+            .output()
+            .map_err(|e| Error::InvalidSysroot(format!("can not run {:?}: {}", rustc, e)))?;
+        if !out.status.success() {
+            return Err(Error::InvalidSysroot(
+                String::from_utf8_lossy(&out.stderr).trim().to_string(),
+            ));
+        }
+        PathBuf::from(String::from_utf8_lossy(&out.stdout).trim())
+    } else {
+        rustc_session::filesearch::get_or_default_sysroot().map_err(Error::InvalidSysroot)?
+    };
+    // Sysroots without rustc (eg built with -Zbuild-std) can not be checked
+    let rustc = sysroot.join("bin").join("rustc");
+    if rustc.exists() {
+        let version = get_rustc_version(rustc.as_os_str())?;
+        if version != rustc_version() {
+            return Err(Error::RustcVersionMismatch {
+                what: format!("the sysroot {}", sysroot.display()),
+                found: version,
+                expected: rustc_version().to_string(),
+            });
+        }
+    }
+    Ok(sysroot)
+}
+
+/// The rmeta of the dependencies can be read only by the same rustc that wrote them
+pub fn check_rustc_versions(manifest: &Manifest) -> Result<(), Error> {
+    for entry in manifest.targets.iter().chain(manifest.deps.values()) {
+        if entry.rustc_version != rustc_version() {
+            return Err(Error::RustcVersionMismatch {
+                what: format!("the crate {}", entry.crate_name),
+                found: entry.rustc_version.clone(),
+                expected: rustc_version().to_string(),
+            });
+        }
     }
+    Ok(())
 }
 
-pub fn log_panic_in_deny_block(call_stack: &[String]) {
-    eprintln!("OMG A PANIC");
-    for funtion in call_stack {
-        eprintln!("    {}\n", funtion);
+/// Set the env and the current dir that rustc had when cargo compiled the crate, so that the
+/// relative paths in the args, `env!` and `include!(concat!(env!("OUT_DIR"), ..))` resolve as
+/// they did during the build. The previous env and current dir are restored when the guard is
+/// dropped.
+pub struct CrateEnv {
+    previous: Vec<(String, Option<String>)>,
+    previous_dir: Option<PathBuf>,
+}
+
+impl CrateEnv {
+    pub fn set(entry: &CrateEntry) -> Result<Self, Error> {
+        let previous_dir = std::env::current_dir().ok();
+        std::env::set_current_dir(&entry.cwd).map_err(|e| Error::CompilerSession {
+            crate_name: entry.crate_name.clone(),
+            reason: format!("can not enter {}: {}", entry.cwd.display(), e),
+        })?;
+        let mut previous = vec![];
+        for (key, value) in &entry.env {
+            previous.push((key.clone(), std::env::var(key).ok()));
+            std::env::set_var(key, value);
+        }
+        Ok(Self {
+            previous,
+            previous_dir,
+        })
     }
 }
 
-pub fn log_allow_panic(call_stack: &[String]) {
-    eprintln!("ATTENTION ALLOW PANIC");
-    for funtion in call_stack {
-        eprintln!("    {}\n", funtion);
+impl Drop for CrateEnv {
+    fn drop(&mut self) {
+        if let Some(dir) = self.previous_dir.take() {
+            let _ = std::env::set_current_dir(dir);
+        }
+        for (key, value) in self.previous.drain(..).rev() {
+            match value {
+                Some(value) => std::env::set_var(key, value),
+                None => std::env::remove_var(key),
+            }
+        }
     }
 }
+
+#[test]
+fn test_externs_from_args() {
+    rustc_span::create_default_session_globals_then(|| {
+        let args: Vec<String> = [
+            "unpanic",
+            "rustc",
+            "--crate-name",
+            "test1_bin",
+            "tests/test1_bin/src/main.rs",
+            "-Zunstable-options",
+            "--extern",
+            "proc_macro",
+            "--extern",
+            "noprelude:test1_lib=/tmp/libtest1_lib-53b4ac4b97d7f3ac.rlib",
+            "--extern",
+            "test2_lib=/tmp/libtest2_lib-53b4ac4b97d7f3ac.rlib",
+        ]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+        let config = config_from_args(&args, Path::new("/tmp")).unwrap();
+        let externs = &config.opts.externs;
+        assert!(externs.get("proc_macro").is_some());
+        assert!(!externs.get("test1_lib").unwrap().add_prelude);
+        assert!(externs.get("test2_lib").unwrap().add_prelude);
+    })
+}